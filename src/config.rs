@@ -0,0 +1,217 @@
+use crate::utils::bart_etd::EtdConfig;
+use crate::utils::feed_source::FeedMode;
+use crate::utils::http_client;
+use crate::utils::logging::LogConfig;
+use crate::utils::train_status::DelayThresholds;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// Server configuration. Read from the JSON file named by the TRMNL_CONFIG environment
+// variable; every field is optional and falls back to the defaults below.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AppConfig {
+    // How long to wait on any upstream API before giving up
+    pub upstream_timeout_ms: u64,
+    pub bart: BartConfig,
+    pub mbta: MbtaConfig,
+    // Any other agency with a static GTFS feed and a GTFS-RT trip-update feed, keyed by the
+    // name used in its URL, e.g. "caltrain" for /transit/caltrain
+    pub agencies: HashMap<String, AgencyConfig>,
+    // Display timezone that daily content (the word of the day) turns over in
+    pub timezone: String,
+    pub log: LogConfig,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            upstream_timeout_ms: 10_000,
+            bart: BartConfig::default(),
+            mbta: MbtaConfig::default(),
+            agencies: HashMap::new(),
+            timezone: "America/Los_Angeles".to_string(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct BartConfig {
+    pub trip_update_url: String,
+    pub feed: FeedMode,
+    // How long a fetched trip-update feed is reused before hitting the upstream again
+    pub cache_seconds: u64,
+    // Trip updates older than this (by their header timestamp) are reported as stale
+    pub stale_after_seconds: u64,
+    pub delay_thresholds: DelayThresholds,
+    pub history: HistoryConfig,
+    // Full BART GTFS with stop_times.txt: an unzipped directory, a .zip file or a zip URL.
+    // The embedded copy has no stop times, so services missing from the realtime feed (the
+    // OAK connector) and the last train come from this. Loaded in the background at startup;
+    // null turns both off.
+    #[serde(alias = "schedule_dir")]
+    pub schedule: Option<String>,
+    // How often the schedule is loaded again to pick up a newly published one
+    pub schedule_refresh_hours: u64,
+    // GTFS-RT service alerts
+    pub alerts_url: String,
+    // Secondary predictions with train lengths, merged into the GTFS-RT ones when enabled
+    pub etd: EtdConfig,
+}
+
+impl Default for BartConfig {
+    fn default() -> Self {
+        BartConfig {
+            trip_update_url: "https://api.bart.gov/gtfsrt/tripupdate.aspx".to_string(),
+            feed: FeedMode::Live,
+            cache_seconds: 30,
+            stale_after_seconds: 5 * 60,
+            delay_thresholds: DelayThresholds::default(),
+            history: HistoryConfig::default(),
+            schedule: Some("https://www.bart.gov/dev/schedules/google_transit.zip".to_string()),
+            schedule_refresh_hours: 24,
+            alerts_url: "https://api.bart.gov/gtfsrt/alerts.aspx".to_string(),
+            etd: EtdConfig::default(),
+        }
+    }
+}
+
+// On-time performance history sampled from the trip-update feed
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // Off by default: sampling polls BART every `sample_seconds` whether or not anyone asks
+    pub enabled: bool,
+    // JSON file the history is kept in across restarts, null to keep it in memory only
+    pub path: Option<String>,
+    pub sample_seconds: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: false,
+            path: None,
+            sample_seconds: 60,
+        }
+    }
+}
+
+// MBTA V3 API (api-v3.mbta.com) used by the MBTA board
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct MbtaConfig {
+    pub base_url: String,
+    // Optional, but without one the API allows only 20 requests a minute
+    pub api_key: Option<String>,
+    // How many times a rate-limited (429) request is retried after backing off
+    pub rate_limit_retries: u32,
+    // Unzipped full MBTA GTFS to resolve stations from instead of the embedded station extract
+    pub gtfs_dir: Option<String>,
+    // Where departures come from: the V3 API, or the keyless GTFS-RT protobuf feeds below
+    pub source: MbtaSource,
+    pub trip_updates_url: String,
+    pub vehicle_positions_url: String,
+    // How long fetched GTFS-RT feeds are reused before hitting the upstream again
+    pub cache_seconds: u64,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MbtaSource {
+    #[default]
+    V3Api,
+    GtfsRt,
+}
+
+impl Default for MbtaConfig {
+    fn default() -> Self {
+        MbtaConfig {
+            base_url: "https://api-v3.mbta.com".to_string(),
+            api_key: None,
+            rate_limit_retries: 2,
+            gtfs_dir: None,
+            source: MbtaSource::V3Api,
+            trip_updates_url: "https://cdn.mbta.com/realtime/TripUpdates.pb".to_string(),
+            vehicle_positions_url: "https://cdn.mbta.com/realtime/VehiclePositions.pb".to_string(),
+            cache_seconds: 30,
+        }
+    }
+}
+
+// A GTFS agency served by /transit/{agency} with the same board as BART
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AgencyConfig {
+    // Display name, e.g. "Caltrain"; defaults to the agency's key
+    pub name: Option<String>,
+    // Static GTFS: an unzipped directory, a .zip file, or an http(s) URL of a zip
+    pub gtfs: String,
+    pub trip_updates_url: String,
+    // GTFS-RT service alerts, if the agency publishes them
+    pub alerts_url: Option<String>,
+    // Sent with every request for the feeds above, e.g. an API key header
+    pub headers: HashMap<String, String>,
+    // Overrides agency_timezone from agency.txt, e.g. "America/Los_Angeles"
+    pub timezone: Option<String>,
+    // How long a fetched trip-update feed is reused before hitting the upstream again
+    pub cache_seconds: u64,
+    // Trip updates older than this (by their header timestamp) are reported as stale
+    pub stale_after_seconds: u64,
+    pub delay_thresholds: DelayThresholds,
+}
+
+impl Default for AgencyConfig {
+    fn default() -> Self {
+        AgencyConfig {
+            name: None,
+            gtfs: String::new(),
+            trip_updates_url: String::new(),
+            alerts_url: None,
+            headers: HashMap::new(),
+            timezone: None,
+            cache_seconds: 30,
+            stale_after_seconds: 5 * 60,
+            delay_thresholds: DelayThresholds::default(),
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn load() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        match std::env::var("TRMNL_CONFIG") {
+            Ok(path) => {
+                let content = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+                let config: AppConfig = serde_json::from_str(&content)?;
+                config.validate()?;
+                Ok(config)
+            }
+            Err(_) => Ok(AppConfig::default()),
+        }
+    }
+
+    // Settings that parse but can't work, caught at startup rather than on the first request
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bart.etd.enabled && self.bart.etd.api_key.is_none() {
+            return Err("bart.etd.enabled needs bart.etd.api_key".to_string());
+        }
+        if self.timezone.parse::<Tz>().is_err() {
+            return Err(format!("Unknown timezone: {}", self.timezone));
+        }
+        Ok(())
+    }
+
+    // The display timezone; load() has already checked that it parses
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    // Shared HTTP client for upstream requests, honouring upstream_timeout_ms
+    pub fn http_client(&self) -> reqwest::Client {
+        http_client::client(self.upstream_timeout_ms)
+    }
+}
@@ -0,0 +1,86 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt;
+
+// Errors a plugin endpoint can answer with. Each has a stable `code` for templates and
+// clients to branch on, and a short `message` that can go straight onto the display.
+#[derive(Debug, Clone)]
+pub enum AppError {
+    // The request body or query string couldn't be understood
+    BadRequest(String),
+    StationNotFound(String),
+    // The upstream API couldn't be reached or answered with an error
+    UpstreamUnavailable(String),
+    // The upstream answered, but not with anything we could read
+    DecodeFailed(String),
+    // The newest data we have is too old to show as live
+    DataStale(String),
+}
+
+// JSON body of every error response
+#[derive(Serialize)]
+pub struct ErrorBody {
+    pub error: &'static str,
+    pub message: String,
+    pub detail: String,
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::StationNotFound(_) => "station_not_found",
+            AppError::UpstreamUnavailable(_) => "upstream_unavailable",
+            AppError::DecodeFailed(_) => "decode_failed",
+            AppError::DataStale(_) => "data_stale",
+        }
+    }
+
+    // Short text for the device screen
+    pub fn message(&self) -> String {
+        match self {
+            AppError::BadRequest(_) => "Invalid request".to_string(),
+            AppError::StationNotFound(station) => format!("Unknown station: {}", station),
+            AppError::UpstreamUnavailable(_) => "Live data is unavailable right now".to_string(),
+            AppError::DecodeFailed(_) => "Live data couldn't be read".to_string(),
+            AppError::DataStale(_) => "Live data is out of date".to_string(),
+        }
+    }
+
+    pub fn detail(&self) -> &str {
+        match self {
+            AppError::BadRequest(detail)
+            | AppError::StationNotFound(detail)
+            | AppError::UpstreamUnavailable(detail)
+            | AppError::DecodeFailed(detail)
+            | AppError::DataStale(detail) => detail,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.detail())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl ResponseError for AppError {
+    // Upstream problems stay 500s, the code in the body tells them apart
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) | AppError::StationNotFound(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.code(),
+            message: self.message(),
+            detail: self.detail().to_string(),
+        })
+    }
+}
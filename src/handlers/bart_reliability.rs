@@ -0,0 +1,77 @@
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::tasks::bart_reliability::{ReliabilityStats, BART_RELIABILITY};
+use crate::utils::feed_source::feed_clock;
+use crate::utils::gtfs_helper::BART_GTFS;
+use crate::utils::refresh::RefreshHint;
+use actix_web::{web, HttpResponse};
+use chrono::Days;
+use serde::{Deserialize, Serialize};
+
+// expected query string, both optional: ?line=Yellow&station=Walnut Creek
+#[derive(Deserialize)]
+pub struct BartReliabilityQuery {
+    pub line: Option<String>,
+    pub station: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LineReliability {
+    pub line: String,
+    pub today: ReliabilityStats,
+    // Rolling seven service days including today
+    pub this_week: ReliabilityStats,
+}
+
+#[derive(Serialize)]
+pub struct BartReliabilityResponse {
+    pub station: Option<String>,
+    pub lines: Vec<LineReliability>,
+    // Numbers only move when the sampler takes its next sample
+    pub refresh: RefreshHint,
+}
+
+pub async fn bart_reliability_handler(
+    config: web::Data<AppConfig>,
+    query: web::Query<BartReliabilityQuery>,
+) -> Result<HttpResponse, AppError> {
+    let station = match &query.station {
+        Some(name) => match BART_GTFS.find_station(name) {
+            Some(station) => Some(station),
+            None => return Err(AppError::StationNotFound(name.clone())),
+        },
+        None => None,
+    };
+
+    let now = feed_clock(&config.bart.feed).await;
+    let today = BART_GTFS.service_date(now);
+    let week_start = today.checked_sub_days(Days::new(6)).unwrap_or(today);
+
+    let history = BART_RELIABILITY.read().await;
+    let lines = match &query.line {
+        // A line with no departures yet still gets an (empty) entry for the widget
+        Some(line) => vec![history
+            .lines()
+            .into_iter()
+            .find(|known| known.eq_ignore_ascii_case(line))
+            .unwrap_or_else(|| line.clone())],
+        None => history.lines(),
+    };
+
+    let station_id = station.map(|station| station.stop_id.as_str());
+    let lines = lines
+        .into_iter()
+        .map(|line| LineReliability {
+            today: (&history.totals(&line, station_id, today)).into(),
+            this_week: (&history.totals(&line, station_id, week_start)).into(),
+            line,
+        })
+        .collect();
+
+    let refresh = RefreshHint::after(config.bart.history.sample_seconds as i64, now);
+    Ok(refresh.json_response(&BartReliabilityResponse {
+        station: station.map(|station| station.stop_name.clone()),
+        lines,
+        refresh: refresh.clone(),
+    }))
+}
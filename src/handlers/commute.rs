@@ -0,0 +1,179 @@
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::handlers::bart::BartProvider;
+use crate::handlers::gtfs_agency::GtfsAgencyProvider;
+use crate::handlers::mbta::MbtaProvider;
+use crate::utils::refresh::RefreshHint;
+use crate::utils::transit::{BoardQuery, FromAppConfig, StationBoard, TransitProvider};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+// Lines in `next_departures`, one TRMNL quadrant's worth
+const NEXT_DEPARTURES: usize = 4;
+
+// One stop on the way, e.g. {"agency": "BART", "stop": "Embarcadero", "line": "Yellow", "direction": 1}
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CommuteLeg {
+    // "BART", "MBTA", or an agency configured under `agencies`
+    pub agency: String,
+    pub stop: String,
+    // Whatever that agency's board takes as a line or route filter, every line when missing
+    #[serde(default)]
+    pub line: Option<String>,
+    // GTFS direction_id, both directions when missing
+    #[serde(default)]
+    pub direction: Option<u8>,
+}
+
+// expected body struct
+#[derive(Deserialize)]
+pub struct CommuteRequest {
+    pub legs: Vec<CommuteLeg>,
+    #[serde(default)]
+    pub show_time_not_minutes: bool,
+    // How many departures to return across every leg
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    10
+}
+
+#[derive(Serialize, Clone)]
+pub struct CommuteDeparture {
+    pub agency: String,
+    pub station: String,
+    pub line: String,
+    pub route_id: String,
+    pub destination: String,
+    pub direction_id: Option<u8>,
+    pub departure_time: i64,
+    pub minutes: i64,
+    pub display_time: String,
+    // The agency board's line with the agency in front, e.g. "Caltrain: Local to San Francisco - 5 min"
+    pub summary: String,
+}
+
+#[derive(Serialize)]
+pub struct CommuteResponse {
+    // Always NEXT_DEPARTURES lines, padded with "No data available"
+    pub next_departures: Vec<String>,
+    // Soonest first across every leg
+    pub departures: Vec<CommuteDeparture>,
+    // Legs whose live data couldn't be loaded, e.g. "Caltrain San Francisco: Live data is unavailable right now"
+    pub unavailable: Vec<String>,
+    pub refresh: RefreshHint,
+}
+
+// One leg's departures from its agency's board, in the leg's direction
+async fn leg_departures<P: TransitProvider>(
+    provider: &P,
+    leg: &CommuteLeg,
+    show_time_not_minutes: bool,
+) -> Result<(Vec<CommuteDeparture>, RefreshHint), AppError> {
+    let request = P::board_request(&BoardQuery {
+        station: &leg.stop,
+        line: leg.line.as_deref(),
+        direction: leg.direction,
+        show_time_not_minutes,
+    });
+    let station = provider.resolve_station(P::station_query(&request)).await?;
+    let board = provider.predictions(&station, &request).await?;
+
+    let agency = provider.agency().to_string();
+    let station = P::station_name(&station);
+    let departures = board
+        .departures()
+        .into_iter()
+        .filter(|departure| leg.direction.is_none_or(|direction| departure.direction_id == Some(direction)))
+        .map(|departure| CommuteDeparture {
+            summary: format!("{}: {}", agency, departure.summary),
+            agency: agency.clone(),
+            station: station.clone(),
+            line: departure.line,
+            route_id: departure.route_id,
+            destination: departure.destination,
+            direction_id: departure.direction_id,
+            departure_time: departure.departure_time,
+            minutes: departure.minutes,
+            display_time: departure.display_time,
+        })
+        .collect();
+    Ok((departures, board.refresh().clone()))
+}
+
+async fn agency_departures(
+    config: &AppConfig,
+    leg: &CommuteLeg,
+    show_time_not_minutes: bool,
+) -> Result<(Vec<CommuteDeparture>, RefreshHint), AppError> {
+    if leg.agency.eq_ignore_ascii_case("BART") {
+        leg_departures(&BartProvider::from_config(config), leg, show_time_not_minutes).await
+    } else if leg.agency.eq_ignore_ascii_case("MBTA") {
+        leg_departures(&MbtaProvider::from_config(config), leg, show_time_not_minutes).await
+    } else {
+        let provider = GtfsAgencyProvider::load(config, &leg.agency).await?;
+        leg_departures(&provider, leg, show_time_not_minutes).await
+    }
+}
+
+// POST /commute: departures from several agencies' stops merged into one board. A leg
+// asking for an unknown agency or station fails the request; one whose live data is down
+// is listed in `unavailable` while the other legs still show.
+pub async fn commute_handler(
+    config: web::Data<AppConfig>,
+    json_body: web::Json<CommuteRequest>,
+) -> Result<HttpResponse, AppError> {
+    let data = json_body.into_inner();
+    if data.legs.is_empty() {
+        return Err(AppError::BadRequest("Invalid request body: legs is empty".to_string()));
+    }
+
+    let now = Utc::now();
+    let mut departures = Vec::new();
+    let mut refreshes = Vec::new();
+    let mut unavailable = Vec::new();
+    let mut first_error = None;
+    for leg in data.legs.iter() {
+        match agency_departures(&config, leg, data.show_time_not_minutes).await {
+            Ok((leg_departures, refresh)) => {
+                departures.extend(leg_departures);
+                refreshes.push(refresh);
+            }
+            Err(e @ (AppError::BadRequest(_) | AppError::StationNotFound(_))) => return Err(e),
+            Err(e) => {
+                warn!(agency = %leg.agency, stop = %leg.stop, error = %e.detail(), "commute leg unavailable");
+                unavailable.push(format!("{} {}: {}", leg.agency, leg.stop, e.message()));
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    // Nothing to show at all is an error, not an empty board
+    if refreshes.is_empty()
+        && let Some(e) = first_error
+    {
+        return Err(e);
+    }
+
+    departures.sort_by(|a, b| (a.departure_time, &a.agency).cmp(&(b.departure_time, &b.agency)));
+    departures.truncate(data.limit);
+
+    let mut next_departures: Vec<String> =
+        departures.iter().take(NEXT_DEPARTURES).map(|departure| departure.summary.clone()).collect();
+    next_departures.resize(NEXT_DEPARTURES, "No data available".to_string());
+
+    // Legs that couldn't load are worth retrying soon
+    if !unavailable.is_empty() {
+        refreshes.push(RefreshHint::after(60, now));
+    }
+    let refresh = RefreshHint::earliest(refreshes, now);
+    Ok(refresh.json_response(&CommuteResponse {
+        next_departures,
+        departures,
+        unavailable,
+        refresh: refresh.clone(),
+    }))
+}
@@ -0,0 +1,155 @@
+use crate::config::{AgencyConfig, AppConfig};
+use crate::error::AppError;
+use crate::handlers::bart::{build_board, check_fresh, collect_gtfs_trains, scheduled_trains, BartOutgoingResponse, BoardContext};
+use crate::handlers::transit::{self, AlertsQuery};
+use crate::tasks::agency_feed_cache::{get_agency_trip_updates, AGENCY_DEPARTURES};
+use crate::utils::feed_source::fetch_with_headers;
+use crate::utils::gtfs_helper::{self, GtfsIndex, GtfsStop};
+use crate::utils::transit::{self as transit_util, BoardQuery, TransitAlert, TransitProvider, TransitRequest};
+use actix_web::{web, HttpResponse};
+use chrono_tz::Tz;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+lazy_static::lazy_static! {
+    // Static feeds with a configured timezone in place of agency.txt's, keyed by (source, timezone)
+    static ref TIMEZONE_GTFS: Mutex<HashMap<(String, String), Arc<GtfsIndex>>> = Mutex::new(HashMap::new());
+}
+
+// An agency configured under `agencies`: stations and schedules from its static GTFS,
+// departures from its GTFS-RT trip updates, built into the same board as BART's. Requests
+// are agency-neutral: lines match by route id, short or long name, with none of BART's rules.
+pub struct GtfsAgencyProvider {
+    key: String,
+    agency: AgencyConfig,
+    client: reqwest::Client,
+    gtfs: Arc<GtfsIndex>,
+}
+
+impl GtfsAgencyProvider {
+    // The agency configured under `key` (case doesn't matter), with its static GTFS loaded
+    pub async fn load(config: &AppConfig, key: &str) -> Result<Self, AppError> {
+        let (key, agency) = config
+            .agencies
+            .get_key_value(key)
+            .or_else(|| config.agencies.iter().find(|(name, _)| name.eq_ignore_ascii_case(key)))
+            .ok_or_else(|| AppError::BadRequest(format!("Unknown agency: {}", key)))?;
+
+        let client = config.http_client();
+        let gtfs = gtfs_helper::load_source_cached(&agency.gtfs, &client, &agency.headers)
+            .await
+            .map_err(|e| AppError::UpstreamUnavailable(format!("Failed to load {} GTFS from {}: {}", key, agency.gtfs, e)))?;
+        let gtfs = match &agency.timezone {
+            Some(timezone) if *timezone != gtfs.timezone => {
+                let mut cache = TIMEZONE_GTFS.lock().unwrap();
+                cache
+                    .entry((agency.gtfs.clone(), timezone.clone()))
+                    .or_insert_with(|| {
+                        let mut index = GtfsIndex::clone(&gtfs);
+                        index.timezone = timezone.clone();
+                        Arc::new(index)
+                    })
+                    .clone()
+            }
+            _ => gtfs,
+        };
+
+        Ok(GtfsAgencyProvider {
+            key: key.clone(),
+            agency: agency.clone(),
+            client,
+            gtfs,
+        })
+    }
+}
+
+impl TransitProvider for GtfsAgencyProvider {
+    type Request = TransitRequest;
+    type Station = GtfsStop;
+    type Board = BartOutgoingResponse;
+
+    fn agency(&self) -> &str {
+        self.agency.name.as_deref().unwrap_or(&self.key)
+    }
+
+    fn timezone(&self) -> Tz {
+        self.gtfs.tz()
+    }
+
+    fn board_request(query: &BoardQuery) -> TransitRequest {
+        query.into()
+    }
+
+    fn station_query(request: &TransitRequest) -> &str {
+        &request.station
+    }
+
+    fn station_name(station: &GtfsStop) -> String {
+        station.stop_name.clone()
+    }
+
+    // A parent station by id or name, else any stop by id; plenty of agencies (bus-heavy
+    // ones especially) have stops without parent stations
+    async fn resolve_station(&self, query: &str) -> Result<GtfsStop, AppError> {
+        self.gtfs
+            .find_station(query)
+            .or_else(|| {
+                let stop_id = self.gtfs.parent_station_id(query.trim());
+                self.gtfs.stops.get(&stop_id)
+            })
+            .cloned()
+            .ok_or_else(|| AppError::StationNotFound(query.to_string()))
+    }
+
+    async fn predictions(&self, station: &GtfsStop, request: &TransitRequest) -> Result<BartOutgoingResponse, AppError> {
+        let snapshot = get_agency_trip_updates(&self.client, &self.agency, &self.gtfs).await?;
+        let snapshot = check_fresh(snapshot, self.agency.stale_after_seconds, self.agency())?;
+        let context = BoardContext {
+            gtfs: &self.gtfs,
+            thresholds: &self.agency.delay_thresholds,
+            rail_access: None,
+            schedule: &self.gtfs,
+            line_matches: GtfsIndex::route_matches_line,
+        };
+
+        let mut trains = collect_gtfs_trains(&context, &snapshot.feed, &station.stop_id, request, snapshot.now);
+        trains.extend(scheduled_trains(&context, &station.stop_id, request, snapshot.now));
+        let departures = AGENCY_DEPARTURES.read().await;
+        let memory = departures.get(&self.agency.trip_updates_url);
+        Ok(build_board(&context, station, request, trains, snapshot.now, memory))
+    }
+
+    // Nothing to show for agencies without an alerts feed
+    async fn alerts(&self, station: Option<&GtfsStop>, route: Option<&str>) -> Result<Vec<TransitAlert>, AppError> {
+        let Some(url) = &self.agency.alerts_url else {
+            return Ok(Vec::new());
+        };
+        let snapshot = fetch_with_headers(&self.client, url, &self.agency.headers).await?;
+        let at_station = station.map(|station| |stop_id: &str| self.gtfs.parent_station_id(stop_id) == station.stop_id);
+        let on_route = route.map(|line| |route_id: &str| self.gtfs.route_matches_line(route_id, line));
+        Ok(transit_util::alerts_from_feed(&snapshot.feed, |entity| {
+            transit_util::informs(entity, at_station.as_ref(), on_route.as_ref())
+        }))
+    }
+}
+
+// POST /transit/{agency}, a TransitRequest body against a configured agency
+pub async fn agency_board_handler(
+    config: web::Data<AppConfig>,
+    agency: web::Path<String>,
+    json_body: web::Json<Value>,
+) -> Result<HttpResponse, AppError> {
+    let provider = GtfsAgencyProvider::load(&config, &agency).await?;
+    transit::board_response(&provider, json_body.into_inner()).await
+}
+
+// GET /transit/{agency}/alerts
+pub async fn agency_alerts_handler(
+    config: web::Data<AppConfig>,
+    agency: web::Path<String>,
+    query: web::Query<AlertsQuery>,
+) -> Result<HttpResponse, AppError> {
+    let provider = GtfsAgencyProvider::load(&config, &agency).await?;
+    transit::alerts_response(&provider, &query).await
+}
@@ -1,5 +1,809 @@
-use actix_web::{HttpResponse, Responder};
+use crate::config::{AppConfig, MbtaSource};
+use crate::error::AppError;
+use crate::tasks::mbta_feed_cache::{get_mbta_trip_updates, get_mbta_vehicle_positions, MBTA_DEPARTURES};
+use crate::utils::gtfs_helper::{GtfsIndex, SERVICE_DAY_START_HOUR};
+use crate::utils::mbta_api::{Alert, Included, MbtaClient};
+use crate::utils::mbta_stations::{self, MbtaStation, StationIndex};
+use crate::utils::refresh::RefreshHint;
+use crate::utils::transit::{AlertKind, BoardQuery, Departure, FromAppConfig, StationBoard, TransitAlert, TransitProvider};
+use chrono::{DateTime, Duration, Timelike, Utc};
+use chrono_tz::America::New_York;
+use chrono_tz::Tz;
+use gtfs_realtime::trip_update::stop_time_update::ScheduleRelationship as StopRelationship;
+use gtfs_realtime::trip_descriptor::ScheduleRelationship as TripRelationship;
+use gtfs_realtime::vehicle_position::VehicleStopStatus;
+use gtfs_realtime::FeedMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-pub async fn mbta_handler() -> impl Responder {
-    HttpResponse::Ok().body("MBTA Handler")
+// How many departures the board shows
+const BOARD_TRAINS: usize = 4;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MbtaIncomingRequest {
+    pub station_name: String,
+    #[serde(default)]
+    pub show_time_not_minutes: bool,
+    // Only this route: a route id ("CR-Providence", "Green-B"), line name ("Red Line"),
+    // "Green" for every Green Line branch, or a branch letter ("B")
+    #[serde(default)]
+    pub route: Option<String>,
+    // Only this mode, for stations several modes share (South Station, North Station)
+    #[serde(default)]
+    pub mode: Option<MbtaMode>,
+}
+
+impl MbtaIncomingRequest {
+    fn wants(&self, route_id: &str, line: &str, mode: MbtaMode) -> bool {
+        self.mode.is_none_or(|wanted| wanted == mode)
+            && self.route.as_deref().is_none_or(|route| route_matches(route, route_id, line))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MbtaMode {
+    Subway,
+    CommuterRail,
+    Bus,
+    Ferry,
+}
+
+impl MbtaMode {
+    // From the GTFS route_type, or MBTA's route id conventions when the route isn't known
+    pub fn for_route(route_id: &str, route_type: Option<u16>) -> Self {
+        match route_type {
+            Some(0) | Some(1) => MbtaMode::Subway,
+            Some(2) => MbtaMode::CommuterRail,
+            Some(4) => MbtaMode::Ferry,
+            Some(_) => MbtaMode::Bus,
+            None if route_id.starts_with("CR-") => MbtaMode::CommuterRail,
+            None if route_id.starts_with("Boat-") => MbtaMode::Ferry,
+            None if matches!(route_id, "Red" | "Orange" | "Blue" | "Mattapan") || route_id.starts_with("Green-") => {
+                MbtaMode::Subway
+            }
+            None => MbtaMode::Bus,
+        }
+    }
+}
+
+// Green Line branch letter, e.g. "Green-B" -> "B"
+fn branch(route_id: &str) -> Option<String> {
+    route_id.strip_prefix("Green-").map(str::to_string)
+}
+
+fn route_matches(filter: &str, route_id: &str, line: &str) -> bool {
+    let filter = filter.trim().to_lowercase();
+    let route_id = route_id.to_lowercase();
+    let line = line.to_lowercase();
+    filter.is_empty()
+        || route_id == filter
+        || line == filter
+        // "Green" or "Green Line" cover every branch
+        || route_id.starts_with(&format!("{}-", filter))
+        || line.starts_with(&format!("{} ", filter))
+        || route_id.strip_prefix("green-").is_some_and(|branch| branch == filter)
+}
+
+// One upcoming departure from the station
+#[derive(Serialize, Clone)]
+pub struct MbtaTrain {
+    pub trip_id: String,
+    pub route_id: String,
+    pub line: String,
+    pub destination: String,
+    pub direction_id: Option<u8>,
+    pub departure_time: i64,
+    pub minutes: i64,
+    pub display_time: String,
+    // MBTA's own status text where it gives one, mostly commuter rail ("On time", "Delayed")
+    pub status: Option<String>,
+    pub mode: MbtaMode,
+    // Green Line branch letter
+    pub branch: Option<String>,
+    // Commuter rail track, once it's been announced
+    pub track: Option<String>,
+    // Where the train is now, when it's running and reporting its position
+    pub vehicle: Option<MbtaVehicleLocation>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct MbtaVehicleLocation {
+    // "STOPPED_AT", "INCOMING_AT" or "IN_TRANSIT_TO", relative to `stop_id`
+    pub status: String,
+    pub stop_id: String,
+    pub stop_name: String,
+    // e.g. "Train is at Kendall/MIT"
+    pub text: String,
+}
+
+impl MbtaVehicleLocation {
+    fn new(mode: MbtaMode, status: &str, stop_id: &str, stop_name: String) -> Option<Self> {
+        let vehicle = match mode {
+            MbtaMode::Bus => "Bus",
+            MbtaMode::Ferry => "Boat",
+            _ => "Train",
+        };
+        let text = match status {
+            "STOPPED_AT" => format!("{} is at {}", vehicle, stop_name),
+            "INCOMING_AT" => format!("{} is arriving at {}", vehicle, stop_name),
+            "IN_TRANSIT_TO" => format!("{} is on the way to {}", vehicle, stop_name),
+            _ => return None,
+        };
+        Some(MbtaVehicleLocation {
+            status: status.to_string(),
+            stop_id: stop_id.to_string(),
+            stop_name,
+            text,
+        })
+    }
+}
+
+// Station name for a vehicle's stop, e.g. platform "70072" -> "Kendall/MIT"
+fn local_stop_name(stations: &StationIndex, stop_id: &str) -> Option<String> {
+    stations
+        .station_for_stop(stop_id)
+        .map(|station| station.name.clone())
+        .or_else(|| stations.gtfs.stops.get(stop_id).map(|_| stations.gtfs.station_name(stop_id)))
+}
+
+impl MbtaTrain {
+    // e.g. "Red Line to Alewife - 3 min" or
+    // "Providence/Stoughton Line to Providence - 4:05 PM, Track 3 (On time)"
+    pub fn summary(&self) -> String {
+        let track = self.track.as_ref().map(|track| format!(", Track {}", track)).unwrap_or_default();
+        let status = self.status.as_ref().map(|status| format!(" ({})", status)).unwrap_or_default();
+        format!(
+            "{}{}{} - {}{}{}",
+            bus_marker(self.mode),
+            self.line,
+            to_destination(&self.destination),
+            self.display_time,
+            track,
+            status
+        )
+    }
+}
+
+// The most recent scheduled departure before now
+#[derive(Serialize, Clone)]
+pub struct MbtaPassedTrain {
+    pub trip_id: String,
+    pub route_id: String,
+    pub line: String,
+    pub destination: String,
+    pub departed_at: i64,
+    pub display_time: String,
+    pub minutes_ago: i64,
+    pub mode: MbtaMode,
+    pub branch: Option<String>,
+}
+
+impl MbtaPassedTrain {
+    pub fn summary(&self) -> String {
+        let ago = match self.minutes_ago {
+            0 => "just now".to_string(),
+            minutes => format!("{} min ago", minutes),
+        };
+        format!("{}{}{} left {}", bus_marker(self.mode), self.line, to_destination(&self.destination), ago)
+    }
+}
+
+// Bus routes are bare numbers ("1", "SL1"), so mark them the way the BART board marks bus bridges
+fn bus_marker(mode: MbtaMode) -> &'static str {
+    match mode {
+        MbtaMode::Bus => "[Bus] ",
+        _ => "",
+    }
+}
+
+// " to Alewife", or nothing when the destination isn't known
+fn to_destination(destination: &str) -> String {
+    if destination.is_empty() {
+        String::new()
+    } else {
+        format!(" to {}", destination)
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct MbtaOutgoingResponse {
+    pub station: String,
+    // Always BOARD_TRAINS entries, padded when fewer trains are coming
+    pub next_trains: Vec<String>,
+    // e.g. "Red Line to Ashmont left 2 min ago"
+    pub passed_train: String,
+    // Where the next train is, e.g. "Train is at Kendall/MIT"
+    pub next_train_location: String,
+    pub last_departed: Option<MbtaPassedTrain>,
+    pub trains: Vec<MbtaTrain>,
+    pub refresh: RefreshHint,
+}
+
+// Line name, headsign and mode for a prediction or schedule, from its included route and trip
+fn line_and_destination(
+    included: &Included,
+    route_id: Option<&String>,
+    trip_id: Option<&String>,
+) -> (String, String, MbtaMode) {
+    let route_id = route_id.map(String::as_str).unwrap_or_default();
+    let route = included.routes.get(route_id);
+    let line = route
+        .map(|route| route.name().to_string())
+        .unwrap_or_else(|| route_id.to_string());
+    let mode = MbtaMode::for_route(route_id, route.map(|route| route.route_type as u16));
+    let destination = trip_id
+        .and_then(|trip_id| included.trips.get(trip_id))
+        .map(|trip| trip.headsign.clone())
+        .unwrap_or_default();
+    (line, destination, mode)
+}
+
+// Tracks are only announced for commuter rail; subway platform codes aren't track numbers
+fn track(mode: MbtaMode, platform_code: Option<&String>) -> Option<String> {
+    match mode {
+        MbtaMode::CommuterRail => platform_code.filter(|code| !code.is_empty()).cloned(),
+        _ => None,
+    }
+}
+
+fn display_time(request: &MbtaIncomingRequest, departure_time: i64, minutes: i64) -> String {
+    if request.show_time_not_minutes {
+        clock_time(departure_time)
+    } else {
+        format!("{} min", minutes)
+    }
+}
+
+fn clock_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .with_timezone(&New_York)
+        .format("%-I:%M %p")
+        .to_string()
+}
+
+async fn upcoming_trains(
+    client: &MbtaClient,
+    stations: &StationIndex,
+    station: &MbtaStation,
+    request: &MbtaIncomingRequest,
+    now: i64,
+) -> Result<Vec<MbtaTrain>, AppError> {
+    let predictions = client
+        .predictions(&[
+            ("filter[stop]", station.id.as_str()),
+            ("include", "route,trip,stop,vehicle"),
+            ("sort", "departure_time"),
+        ])
+        .await?;
+
+    let mut trains: Vec<MbtaTrain> = predictions
+        .data
+        .iter()
+        // Arrival-only predictions are trains terminating here
+        .filter_map(|prediction| Some((prediction, prediction.departure_time?)))
+        .filter(|(_, departure_time)| *departure_time >= now)
+        .filter_map(|(prediction, departure_time)| {
+            let vehicle = prediction
+                .vehicle_id
+                .as_ref()
+                .and_then(|vehicle_id| predictions.included.vehicles.get(vehicle_id))
+                .filter(|vehicle| vehicle.trip_id.is_none() || vehicle.trip_id == prediction.trip_id);
+            let route_id = prediction.route_id.clone().unwrap_or_default();
+            let (line, destination, mode) =
+                line_and_destination(&predictions.included, prediction.route_id.as_ref(), prediction.trip_id.as_ref());
+            if !request.wants(&route_id, &line, mode) {
+                return None;
+            }
+            let platform_code = prediction
+                .stop_id
+                .as_ref()
+                .and_then(|stop_id| predictions.included.stops.get(stop_id))
+                .and_then(|stop| stop.platform_code.as_ref());
+            let minutes = (departure_time - now) / 60;
+            Some(MbtaTrain {
+                trip_id: prediction.trip_id.clone().unwrap_or_default(),
+                branch: branch(&route_id),
+                route_id,
+                line,
+                destination,
+                direction_id: prediction.direction_id,
+                departure_time,
+                minutes,
+                display_time: display_time(request, departure_time, minutes),
+                status: prediction.status.clone().filter(|status| !status.is_empty()),
+                mode,
+                track: track(mode, platform_code),
+                vehicle: vehicle.and_then(|vehicle| {
+                    let stop_id = vehicle.stop_id.as_deref()?;
+                    // Named below when the local GTFS doesn't know the stop
+                    let stop_name = local_stop_name(stations, stop_id).unwrap_or_default();
+                    MbtaVehicleLocation::new(mode, &vehicle.current_status, stop_id, stop_name)
+                }),
+            })
+        })
+        .collect();
+    trains.sort_by_key(|train| train.departure_time);
+
+    // Buses and trains off the embedded extract's map, named by the API in one request
+    let mut unnamed: Vec<&str> = trains
+        .iter()
+        .filter_map(|train| train.vehicle.as_ref())
+        .filter(|vehicle| vehicle.stop_name.is_empty())
+        .map(|vehicle| vehicle.stop_id.as_str())
+        .collect();
+    unnamed.sort();
+    unnamed.dedup();
+    let names: HashMap<String, String> = if unnamed.is_empty() {
+        HashMap::new()
+    } else {
+        match client.stops(&[("filter[id]", unnamed.join(",").as_str())]).await {
+            Ok(stops) => stops.data.into_iter().map(|stop| (stop.id, stop.name)).collect(),
+            // Locations are a nice-to-have, don't fail the board over them
+            Err(_) => HashMap::new(),
+        }
+    };
+    for train in trains.iter_mut() {
+        let mode = train.mode;
+        train.vehicle = train.vehicle.take().and_then(|vehicle| {
+            if !vehicle.stop_name.is_empty() {
+                return Some(vehicle);
+            }
+            let name = names.get(&vehicle.stop_id)?.clone();
+            MbtaVehicleLocation::new(mode, &vehicle.status, &vehicle.stop_id, name)
+        });
+    }
+    Ok(trains)
+}
+
+// Scheduled departures before now, newest first. Predictions drop trains once they leave, so
+// the schedule is the only record of them; a late train is still in the schedule after its
+// time, so callers skip trips that are still predicted.
+async fn scheduled_departures(
+    client: &MbtaClient,
+    station: &MbtaStation,
+    request: &MbtaIncomingRequest,
+    now: i64,
+) -> Result<Vec<MbtaPassedTrain>, AppError> {
+    let (date, max_time) = schedule_window(DateTime::from_timestamp(now, 0).unwrap_or_default().with_timezone(&New_York));
+    let schedules = client
+        .schedules(&[
+            ("filter[stop]", station.id.as_str()),
+            ("filter[date]", date.as_str()),
+            ("filter[max_time]", max_time.as_str()),
+            ("include", "route,trip"),
+            ("sort", "-departure_time"),
+            // Enough that arrival-only trips and other routes' trains don't hide the one we want
+            ("page[limit]", "20"),
+        ])
+        .await?;
+
+    let mut passed: Vec<MbtaPassedTrain> = schedules
+        .data
+        .iter()
+        .filter_map(|schedule| Some((schedule, schedule.departure_time?)))
+        .filter(|(_, departed_at)| *departed_at <= now)
+        .filter_map(|(schedule, departed_at)| {
+            let route_id = schedule.route_id.clone().unwrap_or_default();
+            let (line, destination, mode) =
+                line_and_destination(&schedules.included, schedule.route_id.as_ref(), schedule.trip_id.as_ref());
+            request.wants(&route_id, &line, mode).then(|| MbtaPassedTrain {
+                trip_id: schedule.trip_id.clone().unwrap_or_default(),
+                branch: branch(&route_id),
+                route_id,
+                line,
+                destination,
+                departed_at,
+                display_time: clock_time(departed_at),
+                minutes_ago: (now - departed_at) / 60,
+                mode,
+            })
+        })
+        .collect();
+    passed.sort_by_key(|train| std::cmp::Reverse(train.departed_at));
+    Ok(passed)
+}
+
+// MBTA service date and `filter[max_time]` for schedules up to `at`. Trains after midnight
+// run on the previous day's schedule, which writes their times past 24:00 ("25:10").
+fn schedule_window(at: DateTime<Tz>) -> (String, String) {
+    let service_date = (at - Duration::hours(SERVICE_DAY_START_HOUR)).date_naive();
+    let hours = at.hour() + if at.date_naive() > service_date { 24 } else { 0 };
+    (
+        service_date.format("%Y-%m-%d").to_string(),
+        format!("{:02}:{:02}", hours, at.minute()),
+    )
+}
+
+// Line name for a route in the static feed, read the same way as the V3 API's route names
+fn gtfs_line_name(gtfs: &GtfsIndex, route_id: &str) -> String {
+    match gtfs.routes.get(route_id) {
+        Some(route) if !route.long_name.is_empty() && route.route_type != 3 => route.long_name.clone(),
+        Some(route) if !route.short_name.is_empty() => route.short_name.clone(),
+        _ => route_id.to_string(),
+    }
+}
+
+fn gtfs_mode(gtfs: &GtfsIndex, route_id: &str) -> MbtaMode {
+    MbtaMode::for_route(route_id, gtfs.routes.get(route_id).map(|route| route.route_type))
+}
+
+// Headsign from the static feed, else the station the trip update ends at
+fn gtfs_destination(gtfs: &GtfsIndex, trip_id: &str, last_stop_id: Option<&str>) -> String {
+    match gtfs.trips.get(trip_id) {
+        Some(trip) if !trip.headsign.is_empty() => trip.headsign.clone(),
+        _ => last_stop_id.map(|stop_id| gtfs.station_name(stop_id)).unwrap_or_default(),
+    }
+}
+
+// Departures from the GTFS-RT trip updates. Vehicle positions, when available, add the
+// "Boarding"/"Approaching" statuses the V3 API gives for trains at or near the station.
+fn gtfs_rt_trains(
+    stations: &StationIndex,
+    station: &MbtaStation,
+    request: &MbtaIncomingRequest,
+    trip_updates: &FeedMessage,
+    vehicles: Option<&FeedMessage>,
+    now: i64,
+) -> Vec<MbtaTrain> {
+    // Platforms map to their station; a stop the local GTFS doesn't know can still be the
+    // station itself
+    let at_station = |stop_id: &str| match stations.station_for_stop(stop_id) {
+        Some(found) => found.id == station.id,
+        None => stop_id == station.id,
+    };
+
+    let mut vehicle_status: HashMap<&str, &'static str> = HashMap::new();
+    let mut vehicle_stops: HashMap<&str, (VehicleStopStatus, &str)> = HashMap::new();
+    for vehicle in vehicles.iter().flat_map(|feed| feed.entity.iter()).filter_map(|entity| entity.vehicle.as_ref()) {
+        let (Some(trip_id), Some(stop_id)) = (vehicle.trip.as_ref().and_then(|trip| trip.trip_id.as_deref()), vehicle.stop_id.as_deref())
+        else {
+            continue;
+        };
+        // Missing means IN_TRANSIT_TO, per the GTFS-RT spec
+        let status = vehicle
+            .current_status
+            .and_then(|status| VehicleStopStatus::try_from(status).ok())
+            .unwrap_or(VehicleStopStatus::InTransitTo);
+        vehicle_stops.insert(trip_id, (status, stop_id));
+        if !at_station(stop_id) {
+            continue;
+        }
+        match status {
+            VehicleStopStatus::StoppedAt => vehicle_status.insert(trip_id, "Boarding"),
+            VehicleStopStatus::IncomingAt => vehicle_status.insert(trip_id, "Approaching"),
+            _ => None,
+        };
+    }
+
+    let mut trains = Vec::new();
+    for trip_update in trip_updates.entity.iter().filter_map(|entity| entity.trip_update.as_ref()) {
+        let trip = &trip_update.trip;
+        if trip.schedule_relationship == Some(TripRelationship::Canceled as i32) {
+            continue;
+        }
+        let trip_id = trip.trip_id.clone().unwrap_or_default();
+        let route_id = trip.route_id.clone().unwrap_or_default();
+        let line = gtfs_line_name(&stations.gtfs, &route_id);
+        let mode = gtfs_mode(&stations.gtfs, &route_id);
+        if !request.wants(&route_id, &line, mode) {
+            continue;
+        }
+
+        for stop_time_update in trip_update.stop_time_update.iter() {
+            let Some(stop_id) = stop_time_update.stop_id.as_deref() else {
+                continue;
+            };
+            if !at_station(stop_id) || stop_time_update.schedule_relationship == Some(StopRelationship::Skipped as i32) {
+                continue;
+            }
+            // Arrival-only updates are trains terminating here
+            let Some(departure_time) = stop_time_update.departure.as_ref().and_then(|event| event.time) else {
+                continue;
+            };
+            if departure_time < now {
+                continue;
+            }
+
+            let last_stop_id = trip_update.stop_time_update.last().and_then(|update| update.stop_id.as_deref());
+            let platform_code = stations.gtfs.stops.get(stop_id).and_then(|stop| stop.platform_code.as_ref());
+            let minutes = (departure_time - now) / 60;
+            trains.push(MbtaTrain {
+                trip_id: trip_id.clone(),
+                route_id: route_id.clone(),
+                line: line.clone(),
+                destination: gtfs_destination(&stations.gtfs, &trip_id, last_stop_id),
+                direction_id: trip.direction_id.map(|direction| direction as u8),
+                departure_time,
+                minutes,
+                display_time: display_time(request, departure_time, minutes),
+                status: vehicle_status.get(trip_id.as_str()).map(|status| status.to_string()),
+                mode,
+                branch: branch(&route_id),
+                track: track(mode, platform_code),
+                // Left out when the stop has no name here, rather than showing its id
+                vehicle: vehicle_stops.get(trip_id.as_str()).and_then(|(status, stop_id)| {
+                    let stop_name = local_stop_name(stations, stop_id)?;
+                    MbtaVehicleLocation::new(mode, status.as_str_name(), stop_id, stop_name)
+                }),
+            });
+        }
+    }
+    trains.sort_by_key(|train| train.departure_time);
+    trains
+}
+
+// Board and passed train from the GTFS-RT feeds. Departed trains are remembered between
+// fetches, since the feed drops them once they leave.
+async fn gtfs_rt_board(
+    config: &AppConfig,
+    stations: &StationIndex,
+    station: &MbtaStation,
+    request: &MbtaIncomingRequest,
+) -> Result<(Vec<MbtaTrain>, Option<MbtaPassedTrain>), AppError> {
+    let (trip_updates, vehicles) = tokio::join!(
+        get_mbta_trip_updates(config, &stations.gtfs),
+        get_mbta_vehicle_positions(config)
+    );
+    let trip_updates = trip_updates?;
+    let now = trip_updates.now.timestamp();
+    // Statuses are a nice-to-have, the board works from trip updates alone
+    let vehicles = vehicles.ok();
+    let trains = gtfs_rt_trains(
+        stations,
+        station,
+        request,
+        &trip_updates.feed,
+        vehicles.as_ref().map(|vehicles| vehicles.feed.as_ref()),
+        now,
+    );
+
+    let last_departed = MBTA_DEPARTURES
+        .read()
+        .await
+        .get(&config.mbta.trip_updates_url)
+        .and_then(|memory| {
+            memory.last_departed(&station.id, now, |train| {
+                let line = gtfs_line_name(&stations.gtfs, &train.route_id);
+                request.wants(&train.route_id, &line, gtfs_mode(&stations.gtfs, &train.route_id))
+            })
+        })
+        .map(|train| MbtaPassedTrain {
+            trip_id: train.trip_id.clone(),
+            route_id: train.route_id.clone(),
+            line: gtfs_line_name(&stations.gtfs, &train.route_id),
+            destination: gtfs_destination(&stations.gtfs, &train.trip_id, None),
+            departed_at: train.departed_at,
+            display_time: clock_time(train.departed_at),
+            minutes_ago: (now - train.departed_at) / 60,
+            mode: gtfs_mode(&stations.gtfs, &train.route_id),
+            branch: branch(&train.route_id),
+        });
+    Ok((trains, last_departed))
+}
+
+// A stop the local GTFS doesn't have, e.g. a bus stop when running off the embedded
+// extract, looked up by id in the V3 API
+async fn api_stop(client: &MbtaClient, stop_id: &str) -> Result<Option<MbtaStation>, AppError> {
+    let stop_id = stop_id.trim();
+    if stop_id.is_empty() || stop_id.contains(char::is_whitespace) {
+        return Ok(None);
+    }
+    let stops = client.stops(&[("filter[id]", stop_id)]).await?;
+    Ok(stops.data.into_iter().next().map(|stop| MbtaStation {
+        id: stop.id,
+        name: stop.name,
+        platforms: Vec::new(),
+        routes: Vec::new(),
+        aliases: Vec::new(),
+    }))
+}
+
+// Effects that are about the station building rather than the trains
+const FACILITY_EFFECTS: [&str; 5] = [
+    "ELEVATOR_CLOSURE",
+    "ESCALATOR_CLOSURE",
+    "ACCESS_ISSUE",
+    "FACILITY_ISSUE",
+    "PARKING_CLOSURE",
+];
+
+impl From<&Alert> for TransitAlert {
+    fn from(alert: &Alert) -> Self {
+        let facility = FACILITY_EFFECTS.contains(&alert.effect.as_str())
+            || alert.informed_entity.iter().any(|entity| entity.facility.is_some());
+        TransitAlert {
+            id: alert.id.clone(),
+            kind: if facility { AlertKind::Facility } else { AlertKind::Service },
+            effect: alert.effect.clone(),
+            severity: alert.severity,
+            active_period: alert.active_period.iter().map(|period| (period.start, period.end)).collect(),
+            header: alert.header.clone(),
+            short_header: Some(alert.short_header.clone()).filter(|header| !header.is_empty()),
+        }
+    }
+}
+
+impl StationBoard for MbtaOutgoingResponse {
+    fn departures(&self) -> Vec<Departure> {
+        self.trains
+            .iter()
+            .map(|train| Departure {
+                line: train.line.clone(),
+                route_id: train.route_id.clone(),
+                destination: train.destination.clone(),
+                direction_id: train.direction_id,
+                departure_time: train.departure_time,
+                minutes: train.minutes,
+                display_time: train.display_time.clone(),
+                summary: train.summary(),
+            })
+            .collect()
+    }
+
+    fn refresh(&self) -> &RefreshHint {
+        &self.refresh
+    }
+}
+
+// MBTA boards from the V3 API or the GTFS-RT feeds (`mbta.source`), alerts from the V3 API
+pub struct MbtaProvider {
+    config: AppConfig,
+}
+
+impl FromAppConfig for MbtaProvider {
+    fn from_config(config: &AppConfig) -> Self {
+        MbtaProvider { config: config.clone() }
+    }
+}
+
+impl TransitProvider for MbtaProvider {
+    type Request = MbtaIncomingRequest;
+    type Station = MbtaStation;
+    type Board = MbtaOutgoingResponse;
+
+    fn agency(&self) -> &str {
+        "MBTA"
+    }
+
+    fn timezone(&self) -> Tz {
+        New_York
+    }
+
+    // The MBTA board has no direction filter, callers filter its departures instead
+    fn board_request(query: &BoardQuery) -> MbtaIncomingRequest {
+        MbtaIncomingRequest {
+            station_name: query.station.to_string(),
+            show_time_not_minutes: query.show_time_not_minutes,
+            route: query.line.map(str::to_string),
+            mode: None,
+        }
+    }
+
+    fn station_query(request: &MbtaIncomingRequest) -> &str {
+        &request.station_name
+    }
+
+    fn station_name(station: &MbtaStation) -> String {
+        station.name.clone()
+    }
+
+    async fn resolve_station(&self, query: &str) -> Result<MbtaStation, AppError> {
+        let stations = mbta_stations::station_index(&self.config).await?;
+        match stations.find(query) {
+            Some(station) => Some(station.clone()),
+            None if self.config.mbta.source == MbtaSource::V3Api => api_stop(&MbtaClient::new(&self.config), query).await?,
+            None => None,
+        }
+        .ok_or_else(|| AppError::StationNotFound(query.to_string()))
+    }
+
+    async fn predictions(&self, station: &MbtaStation, request: &MbtaIncomingRequest) -> Result<MbtaOutgoingResponse, AppError> {
+        let config = &self.config;
+        let stations = mbta_stations::station_index(config).await?;
+        let now = Utc::now();
+        let (trains, last_departed) = match config.mbta.source {
+            MbtaSource::V3Api => {
+                let client = MbtaClient::new(config);
+                let (trains, passed) = tokio::join!(
+                    upcoming_trains(&client, &stations, station, request, now.timestamp()),
+                    scheduled_departures(&client, station, request, now.timestamp())
+                );
+                let trains = trains?;
+                // The board still works without the passed train
+                let last_departed = passed.ok().and_then(|passed| {
+                    passed
+                        .into_iter()
+                        .find(|passed| !trains.iter().any(|train| train.trip_id == passed.trip_id))
+                });
+                (trains, last_departed)
+            }
+            MbtaSource::GtfsRt => gtfs_rt_board(config, &stations, station, request).await?,
+        };
+
+        let mut next_trains: Vec<String> = trains.iter().take(BOARD_TRAINS).map(MbtaTrain::summary).collect();
+        next_trains.resize(BOARD_TRAINS, "No data available".to_string());
+
+        let refresh = match trains.first() {
+            Some(next) if request.show_time_not_minutes => RefreshHint::at(next.departure_time + 30, now),
+            Some(next) => RefreshHint::at((next.departure_time + 30).min(now.timestamp() + 60), now),
+            None => RefreshHint::after(30 * 60, now),
+        };
+
+        Ok(MbtaOutgoingResponse {
+            station: station.name.clone(),
+            next_trains,
+            passed_train: last_departed
+                .as_ref()
+                .map(MbtaPassedTrain::summary)
+                .unwrap_or_else(|| "No recent departures".to_string()),
+            next_train_location: trains
+                .first()
+                .and_then(|train| train.vehicle.as_ref())
+                .map(|vehicle| vehicle.text.clone())
+                .unwrap_or_else(|| "No location available".to_string()),
+            last_departed,
+            trains,
+            refresh,
+        })
+    }
+
+    async fn alerts(&self, station: Option<&MbtaStation>, route: Option<&str>) -> Result<Vec<TransitAlert>, AppError> {
+        let stations = mbta_stations::station_index(&self.config).await?;
+        // Alerts name platforms as often as parent stations
+        let stop_ids = station.map(|station| {
+            std::iter::once(station.id.as_str())
+                .chain(station.platforms.iter().map(|platform| platform.stop_id.as_str()))
+                .collect::<Vec<_>>()
+                .join(",")
+        });
+        // "Green" or "Red Line" become the route ids the API filters on
+        let route_ids = route.map(|route| {
+            let mut ids: Vec<&str> = stations
+                .gtfs
+                .routes
+                .keys()
+                .filter(|id| route_matches(route, id, &gtfs_line_name(&stations.gtfs, id)))
+                .map(String::as_str)
+                .collect();
+            ids.sort();
+            if ids.is_empty() {
+                route.to_string()
+            } else {
+                ids.join(",")
+            }
+        });
+
+        // Facility alerts are informed for activities like USING_WHEELCHAIR, which the
+        // API leaves out unless asked for
+        let mut params = vec![("filter[activity]", "ALL")];
+        if let Some(stop_ids) = &stop_ids {
+            params.push(("filter[stop]", stop_ids));
+        }
+        if let Some(route_ids) = &route_ids {
+            params.push(("filter[route]", route_ids));
+        }
+        let alerts = MbtaClient::new(&self.config).alerts(&params).await?;
+        Ok(alerts.data.iter().map(TransitAlert::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_schedule_window_after_midnight() {
+        let evening = New_York.with_ymd_and_hms(2025, 3, 4, 21, 5, 0).unwrap();
+        assert_eq!(schedule_window(evening), ("2025-03-04".to_string(), "21:05".to_string()));
+
+        // Still the 4th's service day, at 25:10 on its clock
+        let late_night = New_York.with_ymd_and_hms(2025, 3, 5, 1, 10, 0).unwrap();
+        assert_eq!(schedule_window(late_night), ("2025-03-04".to_string(), "25:10".to_string()));
+
+        let morning = New_York.with_ymd_and_hms(2025, 3, 5, 5, 30, 0).unwrap();
+        assert_eq!(schedule_window(morning), ("2025-03-05".to_string(), "05:30".to_string()));
+    }
 }
@@ -1,4 +1,30 @@
 pub mod viet_lang_learn;
 pub mod bart;
+pub mod bart_reliability;
 pub mod mbta;
+pub mod gtfs_agency;
+pub mod commute;
+pub mod transit;
 pub mod check_in;
+
+use crate::error::AppError;
+use actix_web::web;
+use bart::BartProvider;
+use mbta::MbtaProvider;
+
+// Route table shared by the server binary and the integration tests
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    // Bodies and query strings that don't parse get the same JSON errors as the handlers
+    cfg.app_data(web::JsonConfig::default().error_handler(|err, _| AppError::BadRequest(err.to_string()).into()))
+        .app_data(web::QueryConfig::default().error_handler(|err, _| AppError::BadRequest(err.to_string()).into()))
+        .route("/viet-lang-learn", web::get().to(viet_lang_learn::viet_lang_learn_handler))
+        .route("/BART", web::post().to(transit::board_handler::<BartProvider>))
+        .route("/BART/alerts", web::get().to(transit::alerts_handler::<BartProvider>))
+        .route("/BART/reliability", web::get().to(bart_reliability::bart_reliability_handler))
+        .route("/MBTA", web::post().to(transit::board_handler::<MbtaProvider>))
+        .route("/MBTA/alerts", web::get().to(transit::alerts_handler::<MbtaProvider>))
+        .route("/transit/{agency}", web::post().to(gtfs_agency::agency_board_handler))
+        .route("/transit/{agency}/alerts", web::get().to(gtfs_agency::agency_alerts_handler))
+        .route("/commute", web::post().to(commute::commute_handler))
+        .route("/check-in", web::post().to(check_in::check_in_handler));
+}
@@ -0,0 +1,269 @@
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::utils::refresh::RefreshHint;
+use crate::utils::transit::{AlertKind, FromAppConfig, StationBoard, TransitAlert, TransitProvider};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Reverse;
+
+// A TRMNL quadrant fits about three lines of this many characters
+const QUADRANT_LINES: usize = 3;
+const QUADRANT_CHARS: usize = 80;
+
+// Several station queries answered in one payload, e.g. home and work side by side
+#[derive(Deserialize)]
+pub struct MultiRequest<Q> {
+    pub queries: Vec<Q>,
+}
+
+#[derive(Serialize)]
+pub struct Section<Q, B> {
+    pub query: Q,
+    #[serde(flatten)]
+    pub board: B,
+}
+
+#[derive(Serialize)]
+pub struct MultiResponse<Q, B> {
+    pub sections: Vec<Section<Q, B>>,
+    // Earliest refresh any section asks for
+    pub refresh: RefreshHint,
+}
+
+// expected query string, at least one of: ?station=Park Street&route=Red
+#[derive(Deserialize)]
+pub struct AlertsQuery {
+    pub station: Option<String>,
+    // Whatever the agency's board takes as a line or route filter
+    pub route: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AlertSummary {
+    pub id: String,
+    pub kind: AlertKind,
+    pub effect: String,
+    pub severity: u8,
+    // False for alerts that haven't started yet
+    pub active: bool,
+    pub starts_at: Option<i64>,
+    pub ends_at: Option<i64>,
+    // Short enough for a quadrant line, e.g. "From 9:00 PM: Red Line shuttle buses..."
+    pub text: String,
+    pub header: String,
+}
+
+#[derive(Serialize)]
+pub struct AlertsResponse {
+    pub station: Option<String>,
+    pub route: Option<String>,
+    // At most QUADRANT_LINES lines each, most severe first
+    pub service_alerts: Vec<String>,
+    pub facility_alerts: Vec<String>,
+    pub alerts: Vec<AlertSummary>,
+    pub refresh: RefreshHint,
+}
+
+fn parse<T: serde::de::DeserializeOwned>(value: Value) -> Result<T, AppError> {
+    serde_json::from_value(value).map_err(|e| AppError::BadRequest(format!("Invalid request body: {}", e)))
+}
+
+// The body is either one station query, or `{"queries": [...]}` for several boards at once.
+// Every station is resolved before any predictions are fetched.
+pub async fn board_response<P: TransitProvider>(provider: &P, body: Value) -> Result<HttpResponse, AppError> {
+    let multi = body.get("queries").is_some();
+    let queries: Vec<P::Request> = if multi {
+        let data: MultiRequest<P::Request> = parse(body)?;
+        if data.queries.is_empty() {
+            return Err(AppError::BadRequest("Invalid request body: queries is empty".to_string()));
+        }
+        data.queries
+    } else {
+        vec![parse(body)?]
+    };
+
+    let mut stations = Vec::new();
+    for query in queries.iter() {
+        stations.push(provider.resolve_station(P::station_query(query)).await?);
+    }
+
+    let mut sections = Vec::new();
+    for (query, station) in queries.into_iter().zip(stations) {
+        let board = provider.predictions(&station, &query).await?;
+        sections.push(Section { query, board });
+    }
+
+    if multi {
+        let refresh = RefreshHint::earliest(sections.iter().map(|section| section.board.refresh().clone()), Utc::now());
+        Ok(refresh.json_response(&MultiResponse { sections, refresh: refresh.clone() }))
+    } else {
+        let board = &sections[0].board;
+        Ok(board.refresh().json_response(board))
+    }
+}
+
+// POST handler for an agency whose provider comes straight from the app config
+pub async fn board_handler<P: TransitProvider + FromAppConfig>(
+    config: web::Data<AppConfig>,
+    json_body: web::Json<Value>,
+) -> Result<HttpResponse, AppError> {
+    board_response(&P::from_config(&config), json_body.into_inner()).await
+}
+
+// The period in effect now, else the next one to start. None once every period has ended.
+// An alert without periods is in effect until it's taken down.
+fn current_period(alert: &TransitAlert, now: i64) -> Option<(bool, Option<i64>, Option<i64>)> {
+    if alert.active_period.is_empty() {
+        return Some((true, None, None));
+    }
+    let mut upcoming: Option<(bool, Option<i64>, Option<i64>)> = None;
+    for &(start, end) in &alert.active_period {
+        if end.is_some_and(|end| end <= now) {
+            continue;
+        }
+        if start.is_none_or(|start| start <= now) {
+            return Some((true, start, end));
+        }
+        if upcoming.is_none_or(|(_, upcoming_start, _)| start < upcoming_start) {
+            upcoming = Some((false, start, end));
+        }
+    }
+    upcoming
+}
+
+// "9:00 PM" later today, "Sat 9:00 PM" further out
+fn start_time(start: i64, now: i64, tz: &Tz) -> String {
+    let local = |timestamp: i64| DateTime::from_timestamp(timestamp, 0).unwrap_or_default().with_timezone(tz);
+    if local(start).date_naive() == local(now).date_naive() {
+        local(start).format("%-I:%M %p").to_string()
+    } else {
+        local(start).format("%a %-I:%M %p").to_string()
+    }
+}
+
+// Cut at a word boundary so the line fits a quadrant
+fn shorten(text: &str, max_chars: usize) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+    // Positions are in chars throughout, alert text isn't always ASCII
+    let chars: Vec<char> = text.chars().take(max_chars - 1).collect();
+    let end = match chars.iter().rposition(|c| *c == ' ') {
+        Some(space) if space > max_chars / 2 => space,
+        _ => chars.len(),
+    };
+    let cut: String = chars[..end].iter().collect();
+    format!("{}…", cut.trim_end_matches([',', '.', ';', ':', ' ']))
+}
+
+fn summarize(alert: &TransitAlert, now: i64, tz: &Tz) -> Option<AlertSummary> {
+    let (active, starts_at, ends_at) = current_period(alert, now)?;
+    let header = alert
+        .short_header
+        .as_ref()
+        .filter(|header| !header.is_empty())
+        .unwrap_or(&alert.header);
+    let text = match (active, starts_at) {
+        (false, Some(start)) => format!("From {}: {}", start_time(start, now, tz), header),
+        _ => header.clone(),
+    };
+    Some(AlertSummary {
+        id: alert.id.clone(),
+        kind: alert.kind,
+        effect: alert.effect.clone(),
+        severity: alert.severity,
+        active,
+        starts_at,
+        ends_at,
+        text: shorten(&text, QUADRANT_CHARS),
+        header: alert.header.clone(),
+    })
+}
+
+// What's in effect now before what's coming, then most severe, then soonest to start
+// (the most recent first among alerts already in effect)
+fn rank(alerts: &mut [AlertSummary]) {
+    alerts.sort_by_key(|alert| {
+        let start = alert.starts_at.unwrap_or(i64::MIN);
+        let (recent_first, soonest_first) = if alert.active { (Reverse(start), 0) } else { (Reverse(0), start) };
+        (!alert.active, Reverse(alert.severity), recent_first, soonest_first)
+    });
+}
+
+fn lines(alerts: &[AlertSummary], kind: AlertKind, none: &str) -> Vec<String> {
+    let lines: Vec<String> = alerts
+        .iter()
+        .filter(|alert| alert.kind == kind)
+        .take(QUADRANT_LINES)
+        .map(|alert| alert.text.clone())
+        .collect();
+    if lines.is_empty() {
+        vec![none.to_string()]
+    } else {
+        lines
+    }
+}
+
+pub async fn alerts_response<P: TransitProvider>(provider: &P, query: &AlertsQuery) -> Result<HttpResponse, AppError> {
+    if query.station.is_none() && query.route.is_none() {
+        return Err(AppError::BadRequest("Give a station, a route, or both".to_string()));
+    }
+    let station = match &query.station {
+        Some(name) => Some(provider.resolve_station(name).await?),
+        None => None,
+    };
+    let alerts = provider.alerts(station.as_ref(), query.route.as_deref()).await?;
+
+    let now = Utc::now();
+    let tz = provider.timezone();
+    let mut summaries: Vec<AlertSummary> =
+        alerts.iter().filter_map(|alert| summarize(alert, now.timestamp(), &tz)).collect();
+    rank(&mut summaries);
+
+    // Alerts change whenever the agency posts them, so check every few minutes, and right
+    // when one starts or ends
+    let changes = summaries
+        .iter()
+        .filter_map(|alert| if alert.active { alert.ends_at } else { alert.starts_at })
+        .map(|at| RefreshHint::at(at, now));
+    let refresh = RefreshHint::earliest(changes.chain([RefreshHint::after(5 * 60, now)]), now);
+
+    Ok(refresh.json_response(&AlertsResponse {
+        station: station.as_ref().map(P::station_name),
+        route: query.route.clone(),
+        service_alerts: lines(&summaries, AlertKind::Service, "No service alerts"),
+        facility_alerts: lines(&summaries, AlertKind::Facility, "Elevators and escalators working"),
+        alerts: summaries,
+        refresh: refresh.clone(),
+    }))
+}
+
+pub async fn alerts_handler<P: TransitProvider + FromAppConfig>(
+    config: web::Data<AppConfig>,
+    query: web::Query<AlertsQuery>,
+) -> Result<HttpResponse, AppError> {
+    alerts_response(&P::from_config(&config), &query).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shorten_counts_chars() {
+        assert_eq!(shorten("Shuttle  buses\nreplace trains", 80), "Shuttle buses replace trains");
+
+        // Every char here is two bytes; a byte-based cut would stop at half the length
+        let headline = "Đường Đỏ: xe buýt thay thế tàu giữa Ashmont và JFK/UMass do sửa chữa đường ray";
+        let short = shorten(headline, 40);
+        assert_eq!(short, "Đường Đỏ: xe buýt thay thế tàu giữa…");
+        assert!(short.chars().count() <= 40);
+
+        // The only space is past half the limit in bytes but not in chars: cut mid-word
+        assert_eq!(shorten("éééééééé abcdefghijklmnop", 20), "éééééééé abcdefghij…");
+    }
+}
@@ -1,29 +1,44 @@
-use crate::tasks::viet_lang_learn_poller::{get_current_viet_data, initialize_cache};
-use actix_web::{HttpResponse, Responder};
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::tasks::viet_lang_learn_poller::{get_current_viet_data, initialize_cache, VietLangResponse};
+use crate::utils::refresh::RefreshHint;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use serde::Serialize;
 
-pub async fn viet_lang_learn_handler() -> impl Responder {
+#[derive(Serialize)]
+struct VietLangBody {
+    #[serde(flatten)]
+    data: VietLangResponse,
+    // The word changes at local midnight
+    refresh: RefreshHint,
+}
+
+fn respond(config: &AppConfig, data: VietLangResponse) -> HttpResponse {
+    let refresh = RefreshHint::next_midnight(Utc::now(), &config.tz());
+    refresh.json_response(&VietLangBody {
+        data,
+        refresh: refresh.clone(),
+    })
+}
+
+pub async fn viet_lang_learn_handler(config: web::Data<AppConfig>) -> Result<HttpResponse, AppError> {
     // Get cached data from the poller
+    if let Some(data) = get_current_viet_data().await {
+        // Return the cached JSON data
+        return Ok(respond(&config, data));
+    }
+
+    // Cache is empty, try to initialize it
+    initialize_cache(&config.tz())
+        .await
+        .map_err(|e| AppError::UpstreamUnavailable(format!("Vietnamese language data not available: {}", e)))?;
+
+    // Try again after initialization
     match get_current_viet_data().await {
-        Some(data) => {
-            // Return the cached JSON data
-            HttpResponse::Ok().json(data)
-        }
-        None => {
-            // Cache is empty, try to initialize it
-            if let Ok(()) = initialize_cache().await {
-                // Try again after initialization
-                match get_current_viet_data().await {
-                    Some(data) => HttpResponse::Ok().json(data),
-                    None => HttpResponse::InternalServerError().json(serde_json::json!({
-                        "error": "Failed to load Vietnamese language data"
-                    })),
-                }
-            } else {
-                // Initialization failed
-                HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": "Vietnamese language data not available"
-                }))
-            }
-        }
+        Some(data) => Ok(respond(&config, data)),
+        None => Err(AppError::UpstreamUnavailable(
+            "Failed to load Vietnamese language data".to_string(),
+        )),
     }
 }
@@ -1,3 +1,5 @@
+pub mod config;
+pub mod error;
 pub mod handlers;
 pub mod tasks;
 pub mod utils;
@@ -1,22 +1,33 @@
 use actix_web::{web, App, HttpServer};
-mod handlers;
-mod tasks;
-mod utils;
+use trmnl_plugin_server::config::AppConfig;
+use tracing_actix_web::TracingLogger;
+use trmnl_plugin_server::utils::logging;
+use trmnl_plugin_server::{handlers, tasks};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let config = AppConfig::load().map_err(std::io::Error::other)?;
+    logging::init(&config.log);
+    let config = web::Data::new(config);
+
     // Start the daily poller in the background
-    tokio::spawn(tasks::viet_lang_learn_poller::run_daily_poller());
+    tokio::spawn(tasks::viet_lang_learn_poller::run_daily_poller(config.tz()));
+    // Load the BART schedule now and keep it current, boards use whatever has loaded
+    tokio::spawn(tasks::bart_schedule::run_schedule_loader(config.get_ref().clone()));
+    // Sample BART delays for the reliability history, if turned on
+    if config.bart.history.enabled {
+        tokio::spawn(tasks::bart_reliability::run_reliability_sampler(config.get_ref().clone()));
+    }
     
     // start the server
-    HttpServer::new(|| {
+    HttpServer::new(move || {
         App::new()
-            .route("/viet-lang-learn", web::get().to(handlers::viet_lang_learn::viet_lang_learn_handler))
-            .route("/BART", web::post().to(handlers::bart::bart_handler))
-            .route("/MBTA", web::post().to(handlers::mbta::mbta_handler))
-            .route("/check-in", web::post().to(handlers::check_in::check_in_handler))
+            // One span per request, with a request_id that every log line inside it carries
+            .wrap(TracingLogger::default())
+            .app_data(config.clone())
+            .configure(handlers::configure)
     })
     .bind("0.0.0.0:22991")?
     .run()
     .await
-}
\ No newline at end of file
+}
@@ -0,0 +1,56 @@
+use crate::config::AgencyConfig;
+use crate::error::AppError;
+use crate::tasks::bart_departures::DepartureMemory;
+use crate::tasks::bart_feed_cache::CachedFeed;
+use crate::utils::feed_source::{fetch_with_headers, FeedSnapshot};
+use crate::utils::gtfs_helper::GtfsIndex;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+
+// Trip-update feeds of the agencies configured under `agencies`, keyed by upstream URL like the BART cache
+lazy_static::lazy_static! {
+    pub static ref AGENCY_FEED_CACHE: Arc<RwLock<HashMap<String, CachedFeed>>> = Arc::new(RwLock::new(HashMap::new()));
+    // Departed trains seen in each trip-update feed, keyed by its URL
+    pub static ref AGENCY_DEPARTURES: Arc<RwLock<HashMap<String, DepartureMemory>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+// Trip updates no older than the agency's `cache_seconds`, remembering departures from
+// every fresh fetch. `gtfs` is the agency's static feed.
+pub async fn get_agency_trip_updates(
+    client: &reqwest::Client,
+    agency: &AgencyConfig,
+    gtfs: &GtfsIndex,
+) -> Result<FeedSnapshot, AppError> {
+    let url = &agency.trip_updates_url;
+    {
+        let cache = AGENCY_FEED_CACHE.read().await;
+        if let Some(cached) = cache.get(url)
+            && cached.fetched_at.elapsed() < Duration::from_secs(agency.cache_seconds)
+        {
+            return Ok(FeedSnapshot {
+                feed: cached.feed.clone(),
+                now: Utc::now(),
+            });
+        }
+    }
+
+    let snapshot = fetch_with_headers(client, url, &agency.headers).await?;
+    AGENCY_FEED_CACHE.write().await.insert(
+        url.clone(),
+        CachedFeed {
+            feed: snapshot.feed.clone(),
+            fetched_at: Instant::now(),
+        },
+    );
+    AGENCY_DEPARTURES
+        .write()
+        .await
+        .entry(url.clone())
+        .or_default()
+        .record(&snapshot, gtfs);
+    Ok(snapshot)
+}
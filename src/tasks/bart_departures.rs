@@ -0,0 +1,124 @@
+use crate::tasks::departure_tracker::DepartureTracker;
+use crate::utils::feed_source::FeedSnapshot;
+use crate::utils::gtfs_helper::{GtfsIndex, BART_GTFS};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// How long departed trains are remembered, and how many per station
+const MEMORY_SECONDS: i64 = 2 * 60 * 60;
+const DEPARTURES_PER_STATION: usize = 20;
+
+// A train that has left a station, as last predicted before it dropped out of the feed
+#[derive(Clone, Debug)]
+pub struct DepartedTrain {
+    pub trip_id: String,
+    pub route_id: String,
+    pub direction_id: Option<u8>,
+    pub stop_id: String,
+    pub departed_at: i64,
+}
+
+// Short-term memory of predictions, so trains can still be reported after they leave
+#[derive(Default)]
+pub struct DepartureMemory {
+    tracker: DepartureTracker<DepartedTrain>,
+    // parent station -> departed trains, newest first
+    departed: HashMap<String, VecDeque<DepartedTrain>>,
+}
+
+impl DepartureMemory {
+    // Fold one feed snapshot in, moving trains that have left to their station's list.
+    // `gtfs` is the agency's static feed, for trip routes and platform parents.
+    pub fn record(&mut self, snapshot: &FeedSnapshot, gtfs: &GtfsIndex) {
+        let now = snapshot.now.timestamp();
+
+        for entity in snapshot.feed.entity.iter() {
+            let Some(trip_update) = &entity.trip_update else {
+                continue;
+            };
+            let Some(trip_id) = &trip_update.trip.trip_id else {
+                continue;
+            };
+            let static_trip = gtfs.trips.get(trip_id);
+            let Some(route_id) = static_trip
+                .map(|trip| trip.route_id.clone())
+                .or_else(|| trip_update.trip.route_id.clone())
+            else {
+                continue;
+            };
+            let direction_id = static_trip
+                .and_then(|trip| trip.direction_id)
+                .or(trip_update.trip.direction_id.map(|direction| direction as u8));
+
+            for stop_time_update in trip_update.stop_time_update.iter() {
+                let Some(stop_id) = &stop_time_update.stop_id else {
+                    continue;
+                };
+                let Some(time) = stop_time_update
+                    .departure
+                    .as_ref()
+                    .or(stop_time_update.arrival.as_ref())
+                    .and_then(|event| event.time)
+                else {
+                    continue;
+                };
+
+                self.tracker.observe(
+                    trip_id,
+                    stop_id,
+                    time,
+                    DepartedTrain {
+                        trip_id: trip_id.clone(),
+                        route_id: route_id.clone(),
+                        direction_id,
+                        stop_id: stop_id.clone(),
+                        departed_at: time,
+                    },
+                );
+            }
+        }
+
+        for (_, mut train) in self.tracker.settle(now, MEMORY_SECONDS) {
+            train.departed_at = train.departed_at.min(now);
+            let station = self.departed.entry(gtfs.parent_station_id(&train.stop_id)).or_default();
+            let position = station
+                .iter()
+                .position(|other| other.departed_at <= train.departed_at)
+                .unwrap_or(station.len());
+            station.insert(position, train);
+            station.truncate(DEPARTURES_PER_STATION);
+        }
+
+        for station in self.departed.values_mut() {
+            station.retain(|train| train.departed_at > now - MEMORY_SECONDS);
+        }
+    }
+
+    // Most recent departure from a station up to `now` that passes the filter
+    pub fn last_departed(
+        &self,
+        station_id: &str,
+        now: i64,
+        filter: impl Fn(&DepartedTrain) -> bool,
+    ) -> Option<&DepartedTrain> {
+        self.departed
+            .get(station_id)?
+            .iter()
+            .find(|train| train.departed_at <= now && filter(train))
+    }
+}
+
+// Departure memories, keyed by feed source (upstream URL or replay directory) like the feed cache
+lazy_static::lazy_static! {
+    pub static ref BART_DEPARTURES: Arc<RwLock<HashMap<String, DepartureMemory>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+pub async fn record_departures(source: &str, snapshot: &FeedSnapshot) {
+    BART_DEPARTURES
+        .write()
+        .await
+        .entry(source.to_string())
+        .or_default()
+        .record(snapshot, &BART_GTFS);
+}
@@ -0,0 +1,107 @@
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::tasks::bart_departures::record_departures;
+use crate::utils::bart_etd::{fetch_etd, EtdEstimate};
+use crate::utils::feed_source::{fetch_feed, FeedMode, FeedSnapshot};
+use chrono::Utc;
+use gtfs_realtime::FeedMessage;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+
+pub struct CachedFeed {
+    pub feed: Arc<FeedMessage>,
+    pub fetched_at: Instant,
+}
+
+// Most recently fetched BART trip-update feed, keyed by upstream URL so servers
+// pointed at different upstreams (e.g. in tests) don't share entries
+lazy_static::lazy_static! {
+    pub static ref BART_FEED_CACHE: Arc<RwLock<HashMap<String, CachedFeed>>> = Arc::new(RwLock::new(HashMap::new()));
+    // ETD estimates, keyed by (upstream URL, station) since the API is queried per station
+    pub static ref BART_ETD_CACHE: Arc<RwLock<HashMap<(String, String), CachedEtd>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+pub struct CachedEtd {
+    pub estimates: Arc<Vec<EtdEstimate>>,
+    pub fetched_at: Instant,
+}
+
+// Trip updates no older than `bart.cache_seconds`, fetching only when the cache is stale
+pub async fn get_bart_feed(config: &AppConfig) -> Result<FeedSnapshot, AppError> {
+    // Replays read local files against a virtual clock, nothing worth caching
+    if let FeedMode::Replay { .. } = config.bart.feed {
+        let snapshot = fetch_feed(&config.http_client(), &config.bart.trip_update_url, &config.bart.feed).await?;
+        record_departures(&feed_source_key(config), &snapshot).await;
+        return Ok(snapshot);
+    }
+
+    {
+        let cache = BART_FEED_CACHE.read().await;
+        if let Some(cached) = cache.get(&config.bart.trip_update_url)
+            && cached.fetched_at.elapsed() < Duration::from_secs(config.bart.cache_seconds)
+        {
+            return Ok(FeedSnapshot {
+                feed: cached.feed.clone(),
+                now: Utc::now(),
+            });
+        }
+    }
+
+    refresh_bart_feed(config).await
+}
+
+// Identifies where the feed comes from, so state derived from it isn't mixed between sources
+pub fn feed_source_key(config: &AppConfig) -> String {
+    match &config.bart.feed {
+        FeedMode::Replay { dir, .. } => dir.clone(),
+        _ => config.bart.trip_update_url.clone(),
+    }
+}
+
+// Fetch the feed now and store it in the cache
+pub async fn refresh_bart_feed(config: &AppConfig) -> Result<FeedSnapshot, AppError> {
+    let snapshot = fetch_feed(&config.http_client(), &config.bart.trip_update_url, &config.bart.feed).await?;
+    record_departures(&feed_source_key(config), &snapshot).await;
+
+    if !matches!(config.bart.feed, FeedMode::Replay { .. }) {
+        let mut cache = BART_FEED_CACHE.write().await;
+        cache.insert(
+            config.bart.trip_update_url.clone(),
+            CachedFeed {
+                feed: snapshot.feed.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+    }
+
+    Ok(snapshot)
+}
+
+// ETD estimates for a station, cached for `bart.cache_seconds` like the trip-update feed
+pub async fn get_bart_etd(
+    config: &AppConfig,
+    station_id: &str,
+) -> Result<Arc<Vec<EtdEstimate>>, AppError> {
+    let key = (config.bart.etd.url.clone(), station_id.to_string());
+    {
+        let cache = BART_ETD_CACHE.read().await;
+        if let Some(cached) = cache.get(&key)
+            && cached.fetched_at.elapsed() < Duration::from_secs(config.bart.cache_seconds)
+        {
+            return Ok(cached.estimates.clone());
+        }
+    }
+
+    let estimates = Arc::new(fetch_etd(&config.http_client(), &config.bart.etd, station_id).await?);
+    BART_ETD_CACHE.write().await.insert(
+        key,
+        CachedEtd {
+            estimates: estimates.clone(),
+            fetched_at: Instant::now(),
+        },
+    );
+    Ok(estimates)
+}
@@ -0,0 +1,226 @@
+use crate::config::AppConfig;
+use crate::tasks::bart_feed_cache::get_bart_feed;
+use crate::tasks::departure_tracker::DepartureTracker;
+use crate::utils::feed_source::FeedSnapshot;
+use crate::utils::gtfs_helper::BART_GTFS;
+use crate::utils::train_status::{DelayState, DelayThresholds, TrainStatus};
+use chrono::{DateTime, Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::time::{interval, Duration};
+use tracing::{info, warn};
+
+// Days of history kept on disk, enough for a rolling week plus today
+const RETENTION_DAYS: u64 = 8;
+
+// Departures and delay totals for one line at one station on one service day
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct DelayCounter {
+    pub departures: u32,
+    pub on_time: u32,
+    pub total_delay_seconds: i64,
+}
+
+impl DelayCounter {
+    fn add(&mut self, other: &DelayCounter) {
+        self.departures += other.departures;
+        self.on_time += other.on_time;
+        self.total_delay_seconds += other.total_delay_seconds;
+    }
+}
+
+// What's counted for a stop once its train has left
+#[derive(Clone, Debug)]
+struct PendingDeparture {
+    line: String,
+    station: String,
+    delay_seconds: i32,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ReliabilityHistory {
+    // service date -> line -> station -> counters
+    pub days: BTreeMap<NaiveDate, BTreeMap<String, BTreeMap<String, DelayCounter>>>,
+    // Upcoming stops, counted once their train has left
+    #[serde(skip)]
+    departures: DepartureTracker<PendingDeparture>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReliabilityStats {
+    pub departures: u32,
+    pub on_time_percent: Option<f64>,
+    pub average_delay_seconds: Option<f64>,
+    pub average_delay_minutes: Option<f64>,
+}
+
+impl From<&DelayCounter> for ReliabilityStats {
+    fn from(counter: &DelayCounter) -> Self {
+        let departures = counter.departures as f64;
+        let (on_time_percent, average_delay_seconds) = if counter.departures == 0 {
+            (None, None)
+        } else {
+            (
+                Some((counter.on_time as f64 / departures * 1000.0).round() / 10.0),
+                Some((counter.total_delay_seconds as f64 / departures * 10.0).round() / 10.0),
+            )
+        };
+
+        ReliabilityStats {
+            departures: counter.departures,
+            on_time_percent,
+            average_delay_seconds,
+            average_delay_minutes: average_delay_seconds.map(|seconds| (seconds / 60.0 * 10.0).round() / 10.0),
+        }
+    }
+}
+
+impl ReliabilityHistory {
+    // Fold one feed snapshot into the history: remember every upcoming stop and
+    // count the ones that have departed since last time
+    pub fn record_sample(&mut self, snapshot: &FeedSnapshot, thresholds: &DelayThresholds) {
+        let now = snapshot.now.timestamp();
+
+        for entity in snapshot.feed.entity.iter() {
+            let Some(trip_update) = &entity.trip_update else {
+                continue;
+            };
+            let Some(trip_id) = &trip_update.trip.trip_id else {
+                continue;
+            };
+            let Some(route_id) = BART_GTFS
+                .trips
+                .get(trip_id)
+                .map(|trip| trip.route_id.clone())
+                .or_else(|| trip_update.trip.route_id.clone())
+            else {
+                continue;
+            };
+
+            for stop_time_update in trip_update.stop_time_update.iter() {
+                let Some(stop_id) = &stop_time_update.stop_id else {
+                    continue;
+                };
+                let Some(event) = stop_time_update.departure.as_ref().or(stop_time_update.arrival.as_ref()) else {
+                    continue;
+                };
+                let (Some(predicted_time), Some(delay_seconds)) = (event.time, event.delay) else {
+                    continue;
+                };
+
+                self.departures.observe(
+                    trip_id,
+                    stop_id,
+                    predicted_time,
+                    PendingDeparture {
+                        line: BART_GTFS.line_name(&route_id),
+                        station: BART_GTFS.parent_station_id(stop_id),
+                        delay_seconds,
+                    },
+                );
+            }
+        }
+
+        for (predicted_time, departure) in self.departures.settle(now, 6 * 60 * 60) {
+            // Bucketed by service day, so trains after midnight count toward the evening before
+            let date = BART_GTFS.service_date(DateTime::from_timestamp(predicted_time, 0).unwrap_or_default());
+            let status = TrainStatus::from_delay(Some(departure.delay_seconds), thresholds);
+
+            let counter = self
+                .days
+                .entry(date)
+                .or_default()
+                .entry(departure.line)
+                .or_default()
+                .entry(departure.station)
+                .or_default();
+            counter.departures += 1;
+            if status.state == DelayState::OnTime {
+                counter.on_time += 1;
+            }
+            // Average delay is about lateness, early departures count as zero
+            counter.total_delay_seconds += departure.delay_seconds.max(0) as i64;
+        }
+
+        // Forget old days
+        if let Some(cutoff) = BART_GTFS.service_date(snapshot.now).checked_sub_days(Days::new(RETENTION_DAYS)) {
+            self.days.retain(|date, _| *date > cutoff);
+        }
+    }
+
+    // Totals for a line (optionally a single station) over the service days from `since` on
+    pub fn totals(&self, line: &str, station: Option<&str>, since: NaiveDate) -> DelayCounter {
+        let mut total = DelayCounter::default();
+        for (_, lines) in self.days.range(since..) {
+            let Some(stations) = lines.get(line) else {
+                continue;
+            };
+            for (station_id, counter) in stations.iter() {
+                if station.is_none_or(|wanted| wanted == station_id) {
+                    total.add(counter);
+                }
+            }
+        }
+        total
+    }
+
+    // Every line with any recorded departures
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.days.values().flat_map(|lines| lines.keys().cloned()).collect();
+        lines.sort();
+        lines.dedup();
+        lines
+    }
+}
+
+// Global reliability history, shared by the sampler task and the HTTP handler
+lazy_static::lazy_static! {
+    pub static ref BART_RELIABILITY: Arc<RwLock<ReliabilityHistory>> = Arc::new(RwLock::new(ReliabilityHistory::default()));
+}
+
+async fn load_history(path: &str) -> Result<ReliabilityHistory, Box<dyn std::error::Error + Send + Sync>> {
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+async fn save_history(path: &str, history: &ReliabilityHistory) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, serde_json::to_vec(history)?).await?;
+    Ok(())
+}
+
+// Take one sample of the cached feed into the global history
+pub async fn sample_bart_reliability(config: &AppConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let snapshot = get_bart_feed(config).await?;
+
+    let mut history = BART_RELIABILITY.write().await;
+    history.record_sample(&snapshot, &config.bart.delay_thresholds);
+
+    if let Some(path) = &config.bart.history.path {
+        save_history(path, &history).await?;
+    }
+    Ok(())
+}
+
+pub async fn run_reliability_sampler(config: AppConfig) {
+    // Pick up where the last run left off
+    if let Some(path) = &config.bart.history.path {
+        match load_history(path).await {
+            Ok(history) => *BART_RELIABILITY.write().await = history,
+            Err(e) => info!(path = %path, error = %e, "starting BART reliability history from scratch"),
+        }
+    }
+
+    let mut interval = interval(Duration::from_secs(config.bart.history.sample_seconds.max(1)));
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = sample_bart_reliability(&config).await {
+            warn!(error = %e, "failed to sample BART reliability");
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::config::AppConfig;
+use crate::utils::gtfs_helper::{self, GtfsIndex};
+use crate::utils::http_client;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+// A schedule that failed to load is tried again after this, not after the full refresh interval
+const RETRY_SECONDS: u64 = 10 * 60;
+// The full feed is a few megabytes, more than the upstream timeout allows for on a slow link
+const DOWNLOAD_TIMEOUT_MS: u64 = 2 * 60 * 1000;
+
+lazy_static::lazy_static! {
+    // `bart.schedule` source -> the copy of it last loaded
+    static ref BART_SCHEDULES: RwLock<HashMap<String, Arc<GtfsIndex>>> = RwLock::new(HashMap::new());
+}
+
+// The BART schedule last loaded from `bart.schedule`, if it has loaded yet. Boards never wait
+// on a download; the loader task keeps this current.
+pub fn bart_schedule(config: &AppConfig) -> Option<Arc<GtfsIndex>> {
+    let source = config.bart.schedule.as_ref()?;
+    BART_SCHEDULES.read().unwrap().get(source).cloned()
+}
+
+// Load `bart.schedule` afresh, replacing the copy boards use once it has parsed
+pub async fn refresh_bart_schedule(config: &AppConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(source) = &config.bart.schedule else {
+        return Ok(());
+    };
+    let client = http_client::client(DOWNLOAD_TIMEOUT_MS);
+    let schedule = gtfs_helper::load_source(source, &client, &HashMap::new()).await?;
+    info!(source = %source, trips = schedule.trips.len(), "loaded BART schedule");
+    BART_SCHEDULES.write().unwrap().insert(source.clone(), schedule);
+    Ok(())
+}
+
+pub async fn run_schedule_loader(config: AppConfig) {
+    if config.bart.schedule.is_none() {
+        return;
+    }
+    loop {
+        let wait = match refresh_bart_schedule(&config).await {
+            Ok(()) => config.bart.schedule_refresh_hours.max(1) * 60 * 60,
+            Err(e) => {
+                warn!(source = ?config.bart.schedule, error = %e, "failed to load BART schedule");
+                RETRY_SECONDS
+            }
+        };
+        sleep(Duration::from_secs(wait)).await;
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::{HashMap, HashSet};
+
+// Works out from successive trip-update snapshots which predicted stops have departed.
+// Each snapshot's stop predictions are `observe`d, then `settle` hands back the ones that
+// have left since the last snapshot, with whatever the caller kept alongside each.
+pub struct DepartureTracker<T> {
+    // "trip_id|stop_id" -> latest predicted time, and the caller's data, for stops not yet departed
+    pending: HashMap<String, (i64, T)>,
+    // "trip_id|stop_id" -> predicted time of stops already handed back, so a stale feed can't repeat them
+    departed: HashMap<String, i64>,
+    // Keys observed since the last settle
+    seen: HashSet<String>,
+}
+
+impl<T> Default for DepartureTracker<T> {
+    fn default() -> Self {
+        DepartureTracker {
+            pending: HashMap::new(),
+            departed: HashMap::new(),
+            seen: HashSet::new(),
+        }
+    }
+}
+
+impl<T> DepartureTracker<T> {
+    // A stop's latest prediction in the current snapshot
+    pub fn observe(&mut self, trip_id: &str, stop_id: &str, predicted_time: i64, value: T) {
+        let key = format!("{}|{}", trip_id, stop_id);
+        if self.departed.contains_key(&key) {
+            return;
+        }
+        self.seen.insert(key.clone());
+        self.pending.insert(key, (predicted_time, value));
+    }
+
+    // Stops that departed as of `now`, as (last predicted time, data): the predicted time
+    // has passed, or the stop vanished from the snapshot around when it was due. Stops
+    // that vanish well ahead of time were cancelled or re-planned and are dropped.
+    // Departures older than `remember_seconds` are forgotten.
+    pub fn settle(&mut self, now: i64, remember_seconds: i64) -> Vec<(i64, T)> {
+        let seen = std::mem::take(&mut self.seen);
+        let keys: Vec<String> = self.pending.keys().cloned().collect();
+        let mut departures = Vec::new();
+        for key in keys {
+            let predicted_time = self.pending[&key].0;
+            let vanished = !seen.contains(&key);
+            if !(predicted_time <= now || (vanished && predicted_time <= now + 60)) {
+                if vanished {
+                    self.pending.remove(&key);
+                }
+                continue;
+            }
+
+            let (predicted_time, value) = self.pending.remove(&key).unwrap();
+            self.departed.insert(key, predicted_time);
+            departures.push((predicted_time, value));
+        }
+
+        self.departed.retain(|_, departed_at| *departed_at > now - remember_seconds);
+        departures
+    }
+}
@@ -0,0 +1,65 @@
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::tasks::bart_departures::DepartureMemory;
+use crate::tasks::bart_feed_cache::CachedFeed;
+use crate::utils::feed_source::{fetch_feed, FeedMode, FeedSnapshot};
+use crate::utils::gtfs_helper::GtfsIndex;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+
+// MBTA GTFS-RT feeds (trip updates and vehicle positions), keyed by upstream URL like the BART cache
+lazy_static::lazy_static! {
+    pub static ref MBTA_FEED_CACHE: Arc<RwLock<HashMap<String, CachedFeed>>> = Arc::new(RwLock::new(HashMap::new()));
+    // Departed trains seen in each trip-update feed, keyed by its URL
+    pub static ref MBTA_DEPARTURES: Arc<RwLock<HashMap<String, DepartureMemory>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+// A feed no older than `mbta.cache_seconds`, and whether it was just fetched
+async fn get_feed(config: &AppConfig, url: &str) -> Result<(FeedSnapshot, bool), AppError> {
+    {
+        let cache = MBTA_FEED_CACHE.read().await;
+        if let Some(cached) = cache.get(url)
+            && cached.fetched_at.elapsed() < Duration::from_secs(config.mbta.cache_seconds)
+        {
+            let snapshot = FeedSnapshot {
+                feed: cached.feed.clone(),
+                now: Utc::now(),
+            };
+            return Ok((snapshot, false));
+        }
+    }
+
+    let snapshot = fetch_feed(&config.http_client(), url, &FeedMode::Live).await?;
+    MBTA_FEED_CACHE.write().await.insert(
+        url.to_string(),
+        CachedFeed {
+            feed: snapshot.feed.clone(),
+            fetched_at: Instant::now(),
+        },
+    );
+    Ok((snapshot, true))
+}
+
+// Trip updates, remembering departures from every fresh fetch so the board can show the
+// last train to leave. `gtfs` is the static feed stations were resolved from.
+pub async fn get_mbta_trip_updates(config: &AppConfig, gtfs: &GtfsIndex) -> Result<FeedSnapshot, AppError> {
+    let url = &config.mbta.trip_updates_url;
+    let (snapshot, fresh) = get_feed(config, url).await?;
+    if fresh {
+        MBTA_DEPARTURES
+            .write()
+            .await
+            .entry(url.clone())
+            .or_default()
+            .record(&snapshot, gtfs);
+    }
+    Ok(snapshot)
+}
+
+pub async fn get_mbta_vehicle_positions(config: &AppConfig) -> Result<FeedSnapshot, AppError> {
+    Ok(get_feed(config, &config.mbta.vehicle_positions_url).await?.0)
+}
@@ -1 +1,8 @@
-pub mod viet_lang_learn_poller;
\ No newline at end of file
+pub mod viet_lang_learn_poller;
+pub mod bart_feed_cache;
+pub mod bart_reliability;
+pub mod bart_schedule;
+pub mod bart_departures;
+pub mod departure_tracker;
+pub mod mbta_feed_cache;
+pub mod agency_feed_cache;
@@ -1,9 +1,12 @@
 use crate::utils::csv_reader;
+use crate::utils::refresh::RefreshHint;
 use chrono::{Datelike, Utc};
+use chrono_tz::Tz;
 use serde::Serialize;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use tokio::time::{Duration, interval};
+use tokio::time::{Duration, sleep};
+use tracing::{debug, error, info};
 
 #[derive(Serialize, Clone)]
 pub struct VietLangResponse {
@@ -20,34 +23,32 @@ lazy_static::lazy_static! {
     pub static ref CURRENT_VIET_DATA: Arc<RwLock<Option<VietLangResponse>>> = Arc::new(RwLock::new(None));
 }
 
-pub async fn run_daily_poller() {
-    let mut interval = interval(Duration::from_secs(24 * 60 * 60)); // 24 hours
-
+pub async fn run_daily_poller(tz: Tz) {
     // Load initial data immediately
-    if let Err(e) = update_viet_lang_cache().await {
-        eprintln!("Error loading initial Vietnamese data: {}", e);
+    if let Err(e) = update_viet_lang_cache(&tz).await {
+        error!(error = %e, "failed to load initial Vietnamese data");
     }
 
     loop {
-        interval.tick().await;
+        // The row is picked by local day, so update right after local midnight (which is
+        // also when responses tell devices to refresh)
+        let refresh = RefreshHint::next_midnight(Utc::now(), &tz);
+        sleep(Duration::from_secs(refresh.refresh_in_seconds as u64 + 1)).await;
 
         // Update cache with current day's data
-        if let Err(e) = update_viet_lang_cache().await {
-            eprintln!("Error during daily poll: {}", e);
+        if let Err(e) = update_viet_lang_cache(&tz).await {
+            error!(error = %e, "failed to update Vietnamese data");
         }
     }
 }
 
-async fn update_viet_lang_cache() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Get current date and calculate CSV row number
-    let now = Utc::now();
+async fn update_viet_lang_cache(tz: &Tz) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Get current local date and calculate CSV row number
+    let now = Utc::now().with_timezone(tz);
     let year_day = now.ordinal(); // Day of year (1-365/366)
     let csv_number = ((year_day - 1) % 1000) + 1;
 
-    println!(
-        "Updating Vietnamese language cache for day {}, CSV row {}",
-        year_day, csv_number
-    );
+    debug!(year_day, csv_row = csv_number, "updating Vietnamese language cache");
 
     // Read from embedded CSV data using the generic util function
     let csv_row =
@@ -73,7 +74,7 @@ async fn update_viet_lang_cache() -> Result<(), Box<dyn std::error::Error + Send
         *cache = Some(response.clone());
     }
 
-    println!("Vietnamese language cache updated successfully");
+    info!(csv_row = csv_number, "Vietnamese language cache updated");
     Ok(())
 }
 
@@ -84,6 +85,6 @@ pub async fn get_current_viet_data() -> Option<VietLangResponse> {
 }
 
 // Initialize cache immediately (useful for tests and startup)
-pub async fn initialize_cache() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    update_viet_lang_cache().await
+pub async fn initialize_cache(tz: &Tz) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    update_viet_lang_cache(tz).await
 }
@@ -0,0 +1,189 @@
+use crate::error::AppError;
+use crate::utils::gtfs_helper::BART_GTFS;
+use serde::Deserialize;
+use std::time::Instant;
+use tracing::info;
+
+// BART's legacy real-time departure estimates API (api.bart.gov/api/etd.aspx). Unlike the
+// GTFS-RT feed it reports train length, but only in whole minutes per station.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct EtdConfig {
+    pub enabled: bool,
+    pub url: String,
+    // Required when enabled; BART hands out keys at api.bart.gov
+    pub api_key: Option<String>,
+}
+
+impl Default for EtdConfig {
+    fn default() -> Self {
+        EtdConfig {
+            enabled: false,
+            url: "https://api.bart.gov/api/etd.aspx".to_string(),
+            api_key: None,
+        }
+    }
+}
+
+// One departure estimate, converted out of the API's all-strings JSON
+#[derive(Clone, Debug)]
+pub struct EtdEstimate {
+    pub station: String,
+    pub destination: String,
+    // Line colour as BART's GTFS names it, e.g. "Yellow"
+    pub line: String,
+    // GTFS direction_id: 0 = North, 1 = South
+    pub direction_id: Option<u8>,
+    pub platform: Option<String>,
+    pub departure_time: i64,
+    pub delay_seconds: Option<i32>,
+    pub cars: Option<u32>,
+    pub bikes_allowed: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct EtdResponse {
+    root: EtdRoot,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct EtdRoot {
+    station: Vec<EtdStation>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct EtdStation {
+    abbr: String,
+    etd: Vec<EtdDestination>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct EtdDestination {
+    destination: String,
+    estimate: Vec<RawEstimate>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawEstimate {
+    // Whole minutes, or "Leaving"
+    minutes: String,
+    platform: String,
+    direction: String,
+    length: String,
+    color: String,
+    bikeflag: String,
+    delay: String,
+    cancelflag: String,
+}
+
+// "YELLOW" -> "Yellow", matching the line names derived from routes.txt
+fn line_from_color(color: &str) -> String {
+    let lower = color.to_lowercase();
+    let mut chars = lower.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// Route id for a line and direction, using BART's "<Line>-N"/"<Line>-S" short names
+pub fn route_for(line: &str, direction_id: Option<u8>) -> Option<String> {
+    let suffix = match direction_id? {
+        0 => "-N",
+        _ => "-S",
+    };
+    let mut routes: Vec<&String> = BART_GTFS
+        .routes
+        .values()
+        .filter(|route| route.short_name.eq_ignore_ascii_case(&format!("{}{}", line, suffix)))
+        .map(|route| &route.route_id)
+        .collect();
+    routes.sort();
+    routes.first().map(|route_id| route_id.to_string())
+}
+
+fn parse_estimates(response: EtdResponse, now: i64) -> Vec<EtdEstimate> {
+    let mut estimates = Vec::new();
+    for station in response.root.station {
+        for destination in station.etd {
+            for raw in destination.estimate {
+                if raw.cancelflag == "1" {
+                    continue;
+                }
+                let minutes: i64 = if raw.minutes.eq_ignore_ascii_case("leaving") {
+                    0
+                } else {
+                    match raw.minutes.parse() {
+                        Ok(minutes) => minutes,
+                        Err(_) => continue,
+                    }
+                };
+                let direction_id = match raw.direction.as_str() {
+                    "North" => Some(0),
+                    "South" => Some(1),
+                    _ => None,
+                };
+
+                estimates.push(EtdEstimate {
+                    station: station.abbr.clone(),
+                    destination: destination.destination.clone(),
+                    line: line_from_color(&raw.color),
+                    direction_id,
+                    platform: Some(raw.platform).filter(|platform| !platform.is_empty()),
+                    departure_time: now + minutes * 60,
+                    delay_seconds: raw.delay.parse().ok(),
+                    cars: raw.length.parse().ok(),
+                    bikes_allowed: match raw.bikeflag.as_str() {
+                        "1" => Some(true),
+                        "0" => Some(false),
+                        _ => None,
+                    },
+                });
+            }
+        }
+    }
+    estimates
+}
+
+// Departure estimates for one station (its GTFS parent id doubles as the ETD abbreviation)
+pub async fn fetch_etd(
+    client: &reqwest::Client,
+    config: &EtdConfig,
+    station_id: &str,
+) -> Result<Vec<EtdEstimate>, AppError> {
+    let api_key = config
+        .api_key
+        .as_deref()
+        .ok_or_else(|| AppError::UpstreamUnavailable("No bart.etd.api_key configured".to_string()))?;
+    let started = Instant::now();
+    let response = client
+        .get(&config.url)
+        .query(&[
+            ("cmd", "etd"),
+            ("orig", station_id),
+            ("key", api_key),
+            ("json", "y"),
+        ])
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| AppError::UpstreamUnavailable(format!("Failed to fetch ETD: {}", e)))?;
+
+    let body: EtdResponse = response
+        .json()
+        .await
+        .map_err(|e| AppError::DecodeFailed(format!("Failed to decode ETD response: {}", e)))?;
+
+    let estimates = parse_estimates(body, chrono::Utc::now().timestamp());
+    info!(
+        station = station_id,
+        latency_ms = started.elapsed().as_millis() as u64,
+        estimates = estimates.len(),
+        "fetched BART ETD"
+    );
+    Ok(estimates)
+}
@@ -1,5 +1,6 @@
 use include_dir::{include_dir, Dir};
 use std::collections::HashMap;
+use std::path::Path;
 
 // Embed the storage directory at compile time
 static STORAGE_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/src/storage");
@@ -36,3 +37,36 @@ pub async fn read_embedded_csv_row(filename: &str, row_number: usize) -> Result<
     
     Ok(response)
 }
+
+
+// Read every row of an embedded CSV file (e.g. "bart_gtfs/stops.txt") as string maps
+pub fn read_embedded_csv(filename: &str) -> Result<Vec<HashMap<String, String>>, Box<dyn std::error::Error + Send + Sync>> {
+    let csv_file = STORAGE_DIR.get_file(filename)
+        .ok_or_else(|| format!("{} not found in embedded storage", filename))?;
+
+    let content = csv_file.contents_utf8()
+        .ok_or_else(|| format!("{} is not valid UTF-8", filename))?;
+
+    parse_csv(content.as_bytes())
+}
+
+pub fn embedded_file_exists(filename: &str) -> bool {
+    STORAGE_DIR.get_file(filename).is_some()
+}
+
+// Read every row of a CSV file on disk as string maps
+pub fn read_csv_file(path: &Path) -> Result<Vec<HashMap<String, String>>, Box<dyn std::error::Error + Send + Sync>> {
+    let content = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse_csv(&content)
+}
+
+fn parse_csv(content: &[u8]) -> Result<Vec<HashMap<String, String>>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = csv::Reader::from_reader(content);
+    let mut records = Vec::new();
+    for result in reader.deserialize() {
+        let record: HashMap<String, String> = result?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
@@ -0,0 +1,198 @@
+use crate::error::AppError;
+use chrono::{DateTime, Utc};
+use gtfs_realtime::FeedMessage;
+use prost::Message;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{info, warn};
+
+// Where a GTFS-RT feed comes from. `record` fetches live and also writes every decoded
+// snapshot to `dir`; `replay` serves snapshots from `dir` against a virtual clock.
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum FeedMode {
+    #[default]
+    Live,
+    Record {
+        dir: String,
+    },
+    Replay {
+        dir: String,
+        // Virtual time (unix seconds) at server start, defaults to the first snapshot
+        start_time: Option<i64>,
+        // How fast the virtual clock runs, 0 freezes it at start_time
+        #[serde(default = "default_replay_speed")]
+        speed: f64,
+    },
+}
+
+fn default_replay_speed() -> f64 {
+    1.0
+}
+
+// A decoded feed together with the time it should be interpreted at
+pub struct FeedSnapshot {
+    pub feed: Arc<FeedMessage>,
+    pub now: DateTime<Utc>,
+}
+
+// Real time the replay clock started ticking, shared by every replayed feed
+lazy_static::lazy_static! {
+    static ref REPLAY_STARTED: Instant = Instant::now();
+}
+
+pub async fn fetch_feed(
+    client: &reqwest::Client,
+    url: &str,
+    mode: &FeedMode,
+) -> Result<FeedSnapshot, AppError> {
+    match mode {
+        FeedMode::Live => fetch_live(client, url).await,
+        FeedMode::Record { dir } => {
+            let snapshot = fetch_live(client, url).await?;
+            // A failed write shouldn't take the board down, the live data is still good
+            if let Err(e) = record_snapshot(Path::new(dir), &snapshot).await {
+                warn!(dir = %dir, error = %e, "failed to record feed snapshot");
+            }
+            Ok(snapshot)
+        }
+        FeedMode::Replay { dir, start_time, speed } => replay_snapshot(Path::new(dir), *start_time, *speed).await,
+    }
+}
+
+async fn fetch_live(client: &reqwest::Client, url: &str) -> Result<FeedSnapshot, AppError> {
+    fetch_with_headers(client, url, &HashMap::new()).await
+}
+
+// A live feed from an upstream that wants extra request headers, e.g. an API key
+pub async fn fetch_with_headers(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HashMap<String, String>,
+) -> Result<FeedSnapshot, AppError> {
+    let started = Instant::now();
+    let mut request = client.get(url);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    // An error page from the upstream would otherwise reach the protobuf decoder
+    let response = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| AppError::UpstreamUnavailable(format!("Failed to fetch feed: {}", e)))?;
+
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| AppError::UpstreamUnavailable(format!("Failed to read response: {}", e)))?;
+
+    let feed = FeedMessage::decode(bytes.as_ref())
+        .map_err(|e| AppError::DecodeFailed(format!("Failed to decode protobuf: {}", e)))?;
+
+    info!(
+        url,
+        latency_ms = started.elapsed().as_millis() as u64,
+        bytes = bytes.len(),
+        entities = feed.entity.len(),
+        "fetched GTFS-RT feed"
+    );
+    Ok(FeedSnapshot {
+        feed: Arc::new(feed),
+        now: Utc::now(),
+    })
+}
+
+// Snapshots are stored as `<feed timestamp>.pb` so replay can order them without decoding
+async fn record_snapshot(dir: &Path, snapshot: &FeedSnapshot) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let timestamp = snapshot
+        .feed
+        .header
+        .timestamp
+        .map(|timestamp| timestamp as i64)
+        .unwrap_or_else(|| snapshot.now.timestamp());
+
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(dir.join(format!("{}.pb", timestamp)), snapshot.feed.encode_to_vec()).await?;
+    Ok(())
+}
+
+// All recorded snapshots in a directory, oldest first
+pub async fn list_snapshots(dir: &Path) -> Result<Vec<(i64, PathBuf)>, AppError> {
+    let unreadable = |e: std::io::Error| {
+        AppError::UpstreamUnavailable(format!("Failed to open snapshot directory {}: {}", dir.display(), e))
+    };
+    let mut snapshots = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await.map_err(unreadable)?;
+
+    while let Some(entry) = entries.next_entry().await.map_err(unreadable)? {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("pb") {
+            continue;
+        }
+        if let Some(timestamp) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+            snapshots.push((timestamp, path));
+        }
+    }
+
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+// Virtual time on the replay clock for a snapshot directory
+async fn replay_clock(dir: &Path, start_time: Option<i64>, speed: f64) -> Result<i64, AppError> {
+    let start = match start_time {
+        Some(start) => start,
+        None => match list_snapshots(dir).await?.first() {
+            Some((first_timestamp, _)) => *first_timestamp,
+            None => return Err(AppError::UpstreamUnavailable(format!("No feed snapshots found in {}", dir.display()))),
+        },
+    };
+
+    let elapsed = REPLAY_STARTED.elapsed().as_secs_f64() * speed;
+    Ok(start + elapsed as i64)
+}
+
+// The time a feed's data should be interpreted at: the real clock, or the virtual one when replaying
+pub async fn feed_clock(mode: &FeedMode) -> DateTime<Utc> {
+    if let FeedMode::Replay { dir, start_time, speed } = mode
+        && let Ok(virtual_now) = replay_clock(Path::new(dir), *start_time, *speed).await
+    {
+        return DateTime::from_timestamp(virtual_now, 0).unwrap_or_default();
+    }
+    Utc::now()
+}
+
+async fn replay_snapshot(
+    dir: &Path,
+    start_time: Option<i64>,
+    speed: f64,
+) -> Result<FeedSnapshot, AppError> {
+    let snapshots = list_snapshots(dir).await?;
+    let Some((_, first_path)) = snapshots.first() else {
+        return Err(AppError::UpstreamUnavailable(format!("No feed snapshots found in {}", dir.display())));
+    };
+    let virtual_now = replay_clock(dir, start_time, speed).await?;
+
+    // Latest snapshot taken at or before the virtual clock, or the first one if we're early
+    let path = snapshots
+        .iter()
+        .rev()
+        .find(|(timestamp, _)| *timestamp <= virtual_now)
+        .map(|(_, path)| path)
+        .unwrap_or(first_path);
+
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|e| AppError::UpstreamUnavailable(format!("Failed to read snapshot {}: {}", path.display(), e)))?;
+    let feed = FeedMessage::decode(bytes.as_slice())
+        .map_err(|e| AppError::DecodeFailed(format!("Failed to decode protobuf: {}", e)))?;
+
+    Ok(FeedSnapshot {
+        feed: Arc::new(feed),
+        now: DateTime::from_timestamp(virtual_now, 0).unwrap_or_default(),
+    })
+}
@@ -1,204 +1,548 @@
-use std::collections::HashMap;
-
-pub fn get_gtfs_id_from_station_name_primary(station_name: &str) -> Option<String> {
-    // Create mapping: station name -> GTFS ID
-    // BART GTFS stop IDs follow pattern: [LINE][NUMBER]-[PLATFORM]
-    // A = Airport/Fremont line, L = Lafayette/Richmond line, M = Montgomery/Peninsula line
-    // -1 = southbound/eastbound, -2 = northbound/westbound
-    
-    let station_to_gtfs: HashMap<&str, &str> = [
-        // A line (Airport/Fremont)
-        ("Embarcadero (SF)", "A10"),
-        ("Montgomery St. (SF)", "A20"),
-        ("Powell St. (SF)", "A30"),
-        ("Civic Center (SF)", "A40"),
-        ("24th St. Mission (SF)", "A50"),
-        ("16th St. Mission (SF)", "A60"),
-        ("Glen Park (SF)", "A70"),
-        ("Balboa Park (SF)", "A80"),
-        
-        // L line (Lafayette/Richmond)  
-        ("MacArthur (Oakland)", "L10"),
-        ("19th St. Oakland", "L20"),
-        ("12th St. Oakland City Center", "L30"),
-        ("West Oakland", "L40"),
-        
-        // M line (Montgomery/Peninsula)
-        ("Embarcadero (SF)", "M10"),
-        ("Montgomery St. (SF)", "M16"),
-        ("Powell St. (SF)", "M20"),
-        ("Civic Center (SF)", "M30"),
-        ("16th St. Mission (SF)", "M40"),
-        ("24th St. Mission (SF)", "M50"),
-        ("Glen Park (SF)", "M60"),
-        ("Balboa Park (SF)", "M70"),
-        ("Daly City", "M80"),
-        
-        // Additional common stations
-        ("Embarcadero (SF)", "EMBR"),
-        ("Montgomery St. (SF)", "MONT"),
-        ("Powell St. (SF)", "POWL"),
-        ("Civic Center (SF)", "CIVC"),
-        ("16th St. Mission (SF)", "16TH"),
-        ("24th St. Mission (SF)", "24TH"),
-        ("Glen Park (SF)", "GLEN"),
-        ("Balboa Park (SF)", "BALB"),
-        ("Daly City", "DALY"),
-        ("Colma", "COLM"),
-        ("South San Francisco", "SSAN"),
-        ("San Bruno", "SBRN"),
-        ("San Francisco Int'l Airport", "SFIA"),
-        ("Millbrae", "MLBR"),
-        ("West Oakland", "WOAK"),
-        ("12th St. Oakland City Center", "12TH"),
-        ("19th St. Oakland", "19TH"),
-        ("MacArthur (Oakland)", "MCAR"),
-        ("Ashby (Berkeley)", "ASHB"),
-        ("Downtown Berkeley", "DBRK"),
-        ("North Berkeley", "NBRK"),
-        ("El Cerrito Plaza", "PLZA"),
-        ("El Cerrito del Norte", "DELN"),
-        ("Richmond", "RICH"),
-        ("Rockridge (Oakland)", "ROCK"),
-        ("Orinda", "ORIN"),
-        ("Lafayette", "LAFY"),
-        ("Walnut Creek", "WCRK"),
-        ("Pleasant Hill", "PHIL"),
-        ("Concord", "CONC"),
-        ("North Concord/Martinez", "NCON"),
-        ("Pittsburg/Bay Point", "PITT"),
-        ("Pittsburg Center", "PCTR"),
-        ("Antioch", "ANTC"),
-        ("Lake Merritt (Oakland)", "LAKE"),
-        ("Fruitvale (Oakland)", "FTVL"),
-        ("Coliseum", "COLS"),
-        ("San Leandro", "SANL"),
-        ("Bay Fair (San Leandro)", "BAYF"),
-        ("Hayward", "HAYW"),
-        ("South Hayward", "SHAY"),
-        ("Union City", "UCTY"),
-        ("Fremont", "FRMT"),
-        ("Warm Springs/South Fremont", "WARM"),
-        ("Milpitas", "MLPT"),
-        ("Berryessa / North San Jose", "BERY"),
-        ("Castro Valley", "CAST"),
-        ("West Dublin", "WDUB"),
-        ("Dublin/Pleasanton", "DUBL"),
-        ("Oakland Int'l Airport", "OAKL"),
-    ].iter().cloned().collect();
-
-    // Try exact match first
-    if let Some(id) = station_to_gtfs.get(station_name) {
-        return Some(id.to_string());
-    }
-    
-    // Try case-insensitive match
-    let lower_name = station_name.to_lowercase();
-    for (name, id) in station_to_gtfs.iter() {
-        if name.to_lowercase() == lower_name {
-            return Some(id.to_string());
+use crate::utils::csv_reader;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+type CsvRows = Vec<HashMap<String, String>>;
+
+// Local hour the service day starts at, late-night trips before it belong to the day before
+pub const SERVICE_DAY_START_HOUR: i64 = 3;
+
+// A stop from stops.txt, either a parent station (location_type 1) or one of its platforms
+#[derive(Clone, Debug)]
+pub struct GtfsStop {
+    pub stop_id: String,
+    pub stop_name: String,
+    pub location_type: u8,
+    pub parent_station: Option<String>,
+    pub platform_code: Option<String>,
+    // Where the platform's trains go, e.g. "Alewife" (MBTA's stops.txt has it, BART's doesn't)
+    pub platform_name: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GtfsRoute {
+    pub route_id: String,
+    pub short_name: String,
+    pub long_name: String,
+    pub route_type: u16,
+    pub color: String,
+    // False for routes realtime_routes.txt lists as not in the GTFS-RT feed
+    pub realtime: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct GtfsTrip {
+    pub trip_id: String,
+    pub route_id: String,
+    pub service_id: String,
+    pub headsign: String,
+    pub direction_id: Option<u8>,
+    // 0 = no information, 1 = yes, 2 = no
+    pub wheelchair_accessible: u8,
+    pub bikes_allowed: u8,
+}
+
+// One stop_times.txt row, departure measured from noon minus 12h on the service day
+#[derive(Clone, Debug)]
+pub struct ScheduledStop {
+    pub trip_id: String,
+    pub stop_id: String,
+    pub departure_seconds: i64,
+}
+
+// A calendar.txt entry: which weekdays (Monday first) a service runs, and between which dates
+#[derive(Clone, Debug)]
+pub struct GtfsService {
+    pub weekdays: [bool; 7],
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+}
+
+// Lookup tables built from the static GTFS files of one agency
+#[derive(Clone)]
+pub struct GtfsIndex {
+    pub timezone: String,
+    pub stops: HashMap<String, GtfsStop>,
+    pub routes: HashMap<String, GtfsRoute>,
+    pub trips: HashMap<String, GtfsTrip>,
+    pub services: HashMap<String, GtfsService>,
+    // calendar_dates.txt: (service_id, date) -> exception_type (1 = added, 2 = removed)
+    pub service_exceptions: HashMap<(String, NaiveDate), u8>,
+    // parent station id -> scheduled stops, empty when the feed ships without stop_times.txt
+    pub stop_times: HashMap<String, Vec<ScheduledStop>>,
+}
+
+// Where a static GTFS feed is read from
+enum GtfsSource<'a> {
+    Embedded(&'a str),
+    Disk(&'a Path),
+}
+
+impl GtfsSource<'_> {
+    fn read(&self, file: &str) -> Result<CsvRows, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            GtfsSource::Embedded(dir) => csv_reader::read_embedded_csv(&format!("{}/{}", dir, file)),
+            GtfsSource::Disk(dir) => csv_reader::read_csv_file(&dir.join(file)),
         }
     }
-    
-    // Try partial match (contains)
-    for (name, id) in station_to_gtfs.iter() {
-        if name.to_lowercase().contains(&lower_name) || lower_name.contains(&name.to_lowercase()) {
-            return Some(id.to_string());
+
+    // Optional files are treated as empty when the feed doesn't have them
+    fn read_optional(&self, file: &str) -> Result<CsvRows, Box<dyn std::error::Error + Send + Sync>> {
+        let exists = match self {
+            GtfsSource::Embedded(dir) => csv_reader::embedded_file_exists(&format!("{}/{}", dir, file)),
+            GtfsSource::Disk(dir) => dir.join(file).exists(),
+        };
+        if exists { self.read(file) } else { Ok(Vec::new()) }
+    }
+}
+
+fn parse_gtfs_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y%m%d").ok()
+}
+
+// Empty GTFS columns come through as "", treat those as missing
+fn non_empty(row: &HashMap<String, String>, column: &str) -> Option<String> {
+    row.get(column).filter(|value| !value.is_empty()).cloned()
+}
+
+// GTFS times are "H:MM:SS" and can run past 24:00:00 for trips after midnight
+fn parse_gtfs_time(value: &str) -> Option<i64> {
+    let mut parts = value.trim().split(':').map(|part| part.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+fn column(row: &HashMap<String, String>, column: &str) -> String {
+    row.get(column).cloned().unwrap_or_default()
+}
+
+// Lowercase and strip the punctuation/abbreviation differences between how people
+// type station names and how stops.txt spells them ("Powell St." vs "Powell Street")
+pub fn normalize_name(name: &str) -> String {
+    name.to_lowercase()
+        .replace(['.', '\''], "")
+        .replace(" / ", " ")
+        .replace('/', " ")
+        .replace("street", "st")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl GtfsIndex {
+    // Load an index from a GTFS directory embedded under src/storage (e.g. "bart_gtfs")
+    pub fn load_embedded(dir: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Self::load(GtfsSource::Embedded(dir))
+    }
+
+    // Load an index from an unzipped GTFS directory on disk
+    pub fn load_dir(dir: impl Into<PathBuf>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let dir = dir.into();
+        Self::load(GtfsSource::Disk(&dir))
+    }
+
+    fn load(source: GtfsSource) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let agency = source.read("agency.txt")?;
+        let timezone = agency
+            .first()
+            .and_then(|row| non_empty(row, "agency_timezone"))
+            .unwrap_or_else(|| "UTC".to_string());
+
+        let stops: HashMap<String, GtfsStop> = source
+            .read("stops.txt")?
+            .iter()
+            .map(|row| {
+                let stop = GtfsStop {
+                    stop_id: column(row, "stop_id"),
+                    stop_name: column(row, "stop_name"),
+                    location_type: row
+                        .get("location_type")
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or(0),
+                    parent_station: non_empty(row, "parent_station"),
+                    platform_code: non_empty(row, "platform_code"),
+                    platform_name: non_empty(row, "platform_name"),
+                };
+                (stop.stop_id.clone(), stop)
+            })
+            .collect();
+
+        let not_realtime: HashSet<String> = source
+            .read_optional("realtime_routes.txt")?
+            .iter()
+            .filter(|row| row.get("realtime_enabled").is_some_and(|value| value == "0"))
+            .map(|row| column(row, "route_id"))
+            .collect();
+
+        let routes = source
+            .read("routes.txt")?
+            .iter()
+            .map(|row| {
+                let route = GtfsRoute {
+                    route_id: column(row, "route_id"),
+                    short_name: column(row, "route_short_name"),
+                    long_name: column(row, "route_long_name"),
+                    route_type: row
+                        .get("route_type")
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or(0),
+                    color: column(row, "route_color"),
+                    realtime: !not_realtime.contains(&column(row, "route_id")),
+                };
+                (route.route_id.clone(), route)
+            })
+            .collect();
+
+        let trips = source
+            .read("trips.txt")?
+            .iter()
+            .map(|row| {
+                let trip = GtfsTrip {
+                    trip_id: column(row, "trip_id"),
+                    route_id: column(row, "route_id"),
+                    service_id: column(row, "service_id"),
+                    headsign: column(row, "trip_headsign"),
+                    direction_id: row.get("direction_id").and_then(|value| value.parse().ok()),
+                    wheelchair_accessible: row
+                        .get("wheelchair_accessible")
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or(0),
+                    bikes_allowed: row.get("bikes_allowed").and_then(|value| value.parse().ok()).unwrap_or(0),
+                };
+                (trip.trip_id.clone(), trip)
+            })
+            .collect();
+
+        let services = source
+            .read_optional("calendar.txt")?
+            .iter()
+            .filter_map(|row| {
+                let weekday = |column: &str| row.get(column).is_some_and(|value| value == "1");
+                let service = GtfsService {
+                    weekdays: [
+                        weekday("monday"),
+                        weekday("tuesday"),
+                        weekday("wednesday"),
+                        weekday("thursday"),
+                        weekday("friday"),
+                        weekday("saturday"),
+                        weekday("sunday"),
+                    ],
+                    start_date: parse_gtfs_date(row.get("start_date")?)?,
+                    end_date: parse_gtfs_date(row.get("end_date")?)?,
+                };
+                Some((column(row, "service_id"), service))
+            })
+            .collect();
+
+        let service_exceptions = source
+            .read_optional("calendar_dates.txt")?
+            .iter()
+            .filter_map(|row| {
+                let date = parse_gtfs_date(row.get("date")?)?;
+                let exception_type = row.get("exception_type")?.parse().ok()?;
+                Some(((column(row, "service_id"), date), exception_type))
+            })
+            .collect();
+
+        let mut stop_times: HashMap<String, Vec<ScheduledStop>> = HashMap::new();
+        for row in source.read_optional("stop_times.txt")?.iter() {
+            let Some(departure_seconds) = row
+                .get("departure_time")
+                .or_else(|| row.get("arrival_time"))
+                .and_then(|value| parse_gtfs_time(value))
+            else {
+                continue;
+            };
+            let stop_id = column(row, "stop_id");
+            let station_id = stops
+                .get(&stop_id)
+                .and_then(|stop| stop.parent_station.clone())
+                .unwrap_or_else(|| stop_id.clone());
+            stop_times.entry(station_id).or_default().push(ScheduledStop {
+                trip_id: column(row, "trip_id"),
+                stop_id,
+                departure_seconds,
+            });
         }
+
+        Ok(GtfsIndex {
+            timezone,
+            stops,
+            routes,
+            trips,
+            services,
+            service_exceptions,
+            stop_times,
+        })
+    }
+
+    // The agency's timezone, for showing times and working out service days
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
     }
-    
-    None
-}
-
-pub fn get_gtfs_id_from_station_name(station_name: &str) -> Option<String> {
-    // Create reverse mapping: station name -> GTFS ID
-    let station_to_gtfs: HashMap<&str, &str> = [
-        // A line (Airport/Fremont)
-        ("Embarcadero (SF)", "A10"),
-        ("Montgomery St. (SF)", "A20"),
-        ("Powell St. (SF)", "A30"),
-        ("Civic Center (SF)", "A40"),
-        ("24th St. Mission (SF)", "A50"),
-        ("16th St. Mission (SF)", "A60"),
-        ("Glen Park (SF)", "A70"),
-        ("Balboa Park (SF)", "A80"),
-        
-        // L line (Lafayette/Richmond)  
-        ("MacArthur (Oakland)", "L10"),
-        ("19th St. Oakland", "L20"),
-        ("12th St. Oakland City Center", "L30"),
-        ("West Oakland", "L40"),
-        
-        // M line (Montgomery/Peninsula) - prefer M line for SF stations
-        ("Embarcadero", "M10"),
-        ("Montgomery St.", "M16"),
-        ("Powell St.", "M20"),
-        ("Civic Center", "M30"),
-        ("16th St. Mission", "M40"),
-        ("24th St. Mission", "M50"),
-        ("Glen Park", "M60"),
-        ("Balboa Park", "M70"),
-        ("Daly City", "M80"),
-        
-        // Additional stations
-        ("Colma", "COLM"),
-        ("South San Francisco", "SSAN"),
-        ("San Bruno", "SBRN"),
-        ("San Francisco Int'l Airport", "SFIA"),
-        ("Millbrae", "MLBR"),
-        ("Ashby (Berkeley)", "ASHB"),
-        ("Downtown Berkeley", "DBRK"),
-        ("North Berkeley", "NBRK"),
-        ("El Cerrito Plaza", "PLZA"),
-        ("El Cerrito del Norte", "DELN"),
-        ("Richmond", "RICH"),
-        ("Rockridge (Oakland)", "ROCK"),
-        ("Orinda", "ORIN"),
-        ("Lafayette", "LAFY"),
-        ("Walnut Creek", "WCRK"),
-        ("Pleasant Hill", "PHIL"),
-        ("Concord", "CONC"),
-        ("North Concord/Martinez", "NCON"),
-        ("Pittsburg/Bay Point", "PITT"),
-        ("Pittsburg Center", "PCTR"),
-        ("Antioch", "ANTC"),
-        ("Lake Merritt (Oakland)", "LAKE"),
-        ("Fruitvale (Oakland)", "FTVL"),
-        ("Coliseum", "COLS"),
-        ("San Leandro", "SANL"),
-        ("Bay Fair (San Leandro)", "BAYF"),
-        ("Hayward", "HAYW"),
-        ("South Hayward", "SHAY"),
-        ("Union City", "UCTY"),
-        ("Fremont", "FRMT"),
-        ("Warm Springs/South Fremont", "WARM"),
-        ("Milpitas", "MLPT"),
-        ("Berryessa / North San Jose", "BERY"),
-        ("Castro Valley", "CAST"),
-        ("West Dublin", "WDUB"),
-        ("Dublin/Pleasanton", "DUBL"),
-        ("Oakland Int'l Airport", "OAKL"),
-    ].iter().cloned().collect();
-    
-    // Try exact match first
-    if let Some(id) = station_to_gtfs.get(station_name) {
-        return Some(id.to_string());
-    }
-    
-    // Try case-insensitive match
-    let lower_name = station_name.to_lowercase();
-    for (name, id) in station_to_gtfs.iter() {
-        if name.to_lowercase() == lower_name {
-            return Some(id.to_string());
+
+    // Service day a moment belongs to: trains after midnight still run on the previous
+    // day's schedule, so the day only turns over at SERVICE_DAY_START_HOUR local time
+    pub fn service_date(&self, at: DateTime<Utc>) -> NaiveDate {
+        (at.with_timezone(&self.tz()) - Duration::hours(SERVICE_DAY_START_HOUR)).date_naive()
+    }
+
+    // Parent station id for a platform stop id ("C40-1" -> "WCRK"), or the id itself
+    pub fn parent_station_id(&self, stop_id: &str) -> String {
+        self.stops
+            .get(stop_id)
+            .and_then(|stop| stop.parent_station.clone())
+            .unwrap_or_else(|| stop_id.to_string())
+    }
+
+    // Human readable station name for any stop id, falling back to the id itself
+    pub fn station_name(&self, stop_id: &str) -> String {
+        let parent_id = self.parent_station_id(stop_id);
+        self.stops
+            .get(&parent_id)
+            .or_else(|| self.stops.get(stop_id))
+            .map(|stop| stop.stop_name.clone())
+            .unwrap_or_else(|| stop_id.to_string())
+    }
+
+    // Resolve a user supplied station name or code to its parent station
+    pub fn find_station(&self, query: &str) -> Option<&GtfsStop> {
+        let stations: Vec<&GtfsStop> = self.stops.values().filter(|stop| stop.location_type == 1).collect();
+        let wanted = normalize_name(query);
+
+        // Station code ("WCRK") or exact name match first
+        if let Some(station) = stations.iter().find(|stop| {
+            stop.stop_id.eq_ignore_ascii_case(query.trim()) || normalize_name(&stop.stop_name) == wanted
+        }) {
+            return Some(station);
+        }
+
+        // Then partial matches, preferring the shortest name so "Dublin" doesn't land on "West Dublin"
+        stations
+            .into_iter()
+            .filter(|stop| {
+                let name = normalize_name(&stop.stop_name);
+                !wanted.is_empty() && name.contains(&wanted)
+            })
+            .min_by_key(|stop| (stop.stop_name.len(), stop.stop_id.clone()))
+    }
+
+    // Service ids running on a date. If the embedded feed has expired (no service covers
+    // the date at all) fall back to the services for the same day of the week.
+    pub fn active_services(&self, date: NaiveDate) -> HashSet<String> {
+        let weekday = date.weekday().num_days_from_monday() as usize;
+        let runs_on_weekday = |service: &GtfsService| service.weekdays[weekday];
+
+        let mut active: HashSet<String> = self
+            .services
+            .iter()
+            .filter(|(_, service)| runs_on_weekday(service) && service.start_date <= date && date <= service.end_date)
+            .map(|(service_id, _)| service_id.clone())
+            .collect();
+        for ((service_id, exception_date), exception_type) in self.service_exceptions.iter() {
+            if *exception_date == date {
+                match exception_type {
+                    1 => active.insert(service_id.clone()),
+                    _ => active.remove(service_id),
+                };
+            }
+        }
+
+        let feed_covers_date = self
+            .services
+            .values()
+            .any(|service| service.start_date <= date && date <= service.end_date);
+        if feed_covers_date {
+            return active;
         }
+
+        // Skip one-day special schedules (events, holidays), they aren't the regular pattern
+        self.services
+            .iter()
+            .filter(|(_, service)| runs_on_weekday(service) && service.end_date > service.start_date)
+            .map(|(service_id, _)| service_id.clone())
+            .collect()
+    }
+
+    // Trips scheduled on the given services for a route and direction. trips.txt isn't in any
+    // particular order, so these are sorted by trip id; departure order needs stop_times.txt.
+    pub fn scheduled_trips(&self, route_id: &str, direction_id: Option<u8>, services: &HashSet<String>) -> Vec<&GtfsTrip> {
+        let mut trips: Vec<&GtfsTrip> = self
+            .trips
+            .values()
+            .filter(|trip| {
+                trip.route_id == route_id
+                    && services.contains(&trip.service_id)
+                    && (direction_id.is_none() || trip.direction_id == direction_id)
+            })
+            .collect();
+        trips.sort_by(|a, b| a.trip_id.cmp(&b.trip_id));
+        trips
+    }
+
+    // Scheduled departures (trip, stop, unix time) from a station on one service day
+    pub fn scheduled_departures(&self, station_id: &str, service_date: NaiveDate) -> Vec<(&GtfsTrip, &ScheduledStop, i64)> {
+        let Some(stops) = self.stop_times.get(station_id) else {
+            return Vec::new();
+        };
+        let Some(noon) = service_date
+            .and_hms_opt(12, 0, 0)
+            .and_then(|noon| self.tz().from_local_datetime(&noon).single())
+        else {
+            return Vec::new();
+        };
+        let day_start = (noon - Duration::hours(12)).timestamp();
+        let services = self.active_services(service_date);
+
+        let mut departures: Vec<(&GtfsTrip, &ScheduledStop, i64)> = stops
+            .iter()
+            .filter_map(|stop| {
+                let trip = self.trips.get(&stop.trip_id)?;
+                services
+                    .contains(&trip.service_id)
+                    .then_some((trip, stop, day_start + stop.departure_seconds))
+            })
+            .collect();
+        departures.sort_by_key(|(_, _, time)| *time);
+        departures
     }
-    
-    // Try partial match (contains)
-    for (name, id) in station_to_gtfs.iter() {
-        if name.to_lowercase().contains(&lower_name) || lower_name.contains(&name.to_lowercase()) {
-            return Some(id.to_string());
+
+    // Line name shown to riders, e.g. route short name "Yellow-S" -> "Yellow"
+    pub fn line_name(&self, route_id: &str) -> String {
+        match self.routes.get(route_id) {
+            // Plenty of agencies only fill in the long name
+            Some(route) if route.short_name.is_empty() => route.long_name.clone(),
+            Some(route) => route.short_name.split('-').next().unwrap_or(&route.short_name).to_string(),
+            None => route_id.to_string(),
+        }
+    }
+
+    // Whether a route belongs to the line a user asked for ("Yellow", "yellow-s" or a route id)
+    pub fn route_matches_line(&self, route_id: &str, line: &str) -> bool {
+        let line = line.trim();
+        if line.is_empty() || route_id.eq_ignore_ascii_case(line) {
+            return true;
+        }
+        match self.routes.get(route_id) {
+            Some(route) => {
+                route.short_name.eq_ignore_ascii_case(line) || self.line_name(route_id).eq_ignore_ascii_case(line)
+            }
+            None => false,
         }
     }
-    
-    None
-}
\ No newline at end of file
+}
+
+lazy_static::lazy_static! {
+    pub static ref BART_GTFS: GtfsIndex =
+        GtfsIndex::load_embedded("bart_gtfs").expect("embedded BART GTFS data should parse");
+    // GTFS directories loaded from disk, keyed by path so each is only parsed once
+    static ref DISK_GTFS: Mutex<HashMap<PathBuf, Arc<GtfsIndex>>> = Mutex::new(HashMap::new());
+    // Static GTFS loaded from zips and URLs, keyed by source. Each slot is locked while its
+    // source loads, so concurrent first requests share one download.
+    static ref SOURCE_GTFS: Mutex<HashMap<String, Arc<SourceSlot>>> = Mutex::new(HashMap::new());
+}
+
+// One source's static GTFS, once it has loaded
+type SourceSlot = tokio::sync::Mutex<Option<Arc<GtfsIndex>>>;
+
+// Numbers the temp directories zips are unpacked into
+static UNZIP_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// Load (once) and share a GTFS directory from disk. Parsing a full stop_times.txt takes a
+// while, so it runs off the async runtime.
+pub async fn load_dir_cached(dir: &str) -> Result<Arc<GtfsIndex>, Box<dyn std::error::Error + Send + Sync>> {
+    let path = PathBuf::from(dir);
+    if let Some(index) = DISK_GTFS.lock().unwrap().get(&path) {
+        return Ok(index.clone());
+    }
+
+    let load_path = path.clone();
+    let index = Arc::new(tokio::task::spawn_blocking(move || GtfsIndex::load_dir(load_path)).await??);
+    DISK_GTFS.lock().unwrap().insert(path, index.clone());
+    Ok(index)
+}
+
+// Load (once) static GTFS from wherever an agency publishes it, see load_source. Requests
+// that ask for the same source while it's loading wait for that load instead of starting
+// their own; restart to pick up a newer feed.
+pub async fn load_source_cached(
+    source: &str,
+    client: &reqwest::Client,
+    headers: &HashMap<String, String>,
+) -> Result<Arc<GtfsIndex>, Box<dyn std::error::Error + Send + Sync>> {
+    if Path::new(source).is_dir() {
+        return load_dir_cached(source).await;
+    }
+    let slot = SOURCE_GTFS.lock().unwrap().entry(source.to_string()).or_default().clone();
+    let mut slot = slot.lock().await;
+    if let Some(index) = slot.as_ref() {
+        return Ok(index.clone());
+    }
+    let index = load_source(source, client, headers).await?;
+    *slot = Some(index.clone());
+    Ok(index)
+}
+
+// Load static GTFS afresh from an unzipped directory, a .zip file, or a zip at an http(s)
+// URL fetched with `headers`. Each zip is unpacked into its own temp directory, removed
+// again once parsed, so loads of the same source never share files.
+pub async fn load_source(
+    source: &str,
+    client: &reqwest::Client,
+    headers: &HashMap<String, String>,
+) -> Result<Arc<GtfsIndex>, Box<dyn std::error::Error + Send + Sync>> {
+    if Path::new(source).is_dir() {
+        let dir = PathBuf::from(source);
+        return Ok(Arc::new(tokio::task::spawn_blocking(move || GtfsIndex::load_dir(dir)).await??));
+    }
+
+    let bytes = if source.starts_with("http://") || source.starts_with("https://") {
+        let mut request = client.get(source);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        request.send().await?.error_for_status()?.bytes().await?.to_vec()
+    } else {
+        tokio::fs::read(source).await?
+    };
+
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    let dir = std::env::temp_dir().join("trmnl-gtfs").join(format!(
+        "{:016x}-{}-{}",
+        hasher.finish(),
+        std::process::id(),
+        UNZIP_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let index = tokio::task::spawn_blocking(move || {
+        let index = unzip(&bytes, &dir).and_then(GtfsIndex::load_dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        index
+    })
+    .await??;
+    Ok(Arc::new(index))
+}
+
+// Unpack a GTFS zip into `dir`. Some agencies zip a folder rather than its files, so the
+// feed is wherever stops.txt ended up.
+fn unzip(bytes: &[u8], dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+    std::fs::create_dir_all(dir)?;
+    archive.extract(dir)?;
+    if dir.join("stops.txt").exists() {
+        return Ok(dir.to_path_buf());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.join("stops.txt").exists() {
+            return Ok(path);
+        }
+    }
+    Err(format!("no stops.txt in {}", dir.display()).into())
+}
@@ -0,0 +1,86 @@
+use serde::Serialize;
+
+// Point in the upcoming departures where trains start coming at a different rate
+#[derive(Serialize, Clone, Debug)]
+pub struct HeadwayChange {
+    // Departure (unix time) after which the new headway applies
+    pub at: i64,
+    pub display_time: String,
+    pub headway_minutes: i64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LastTrain {
+    pub trip_id: String,
+    pub destination: String,
+    pub predicted_time: i64,
+    pub display_time: String,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HeadwaySummary {
+    pub current_headway_minutes: Option<i64>,
+    // e.g. "Trains every 20 min"
+    pub frequency_text: String,
+    pub next_change: Option<HeadwayChange>,
+    // Trips the static schedule runs today for the requested line and direction
+    pub scheduled_trips_today: usize,
+    // Whether the schedule has departure times for the station to tell the night's last
+    // train from. BART's embedded GTFS has no stop_times.txt, so while `bart.schedule` can't
+    // be loaded there's no last-train info rather than a guess.
+    pub last_train_known: bool,
+    // Set once the night's final scheduled trip shows up in the live predictions
+    pub last_train: Option<LastTrain>,
+}
+
+// Gaps shorter than this are trains on different branches bunching up, not a headway
+const MIN_GAP_SECONDS: i64 = 60;
+// How many upcoming gaps make up the "current" headway
+const CURRENT_GAPS: usize = 3;
+
+fn median(values: &mut [i64]) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort();
+    Some(values[values.len() / 2])
+}
+
+// Current headway (minutes) from upcoming departure times, and the first later gap that
+// differs from it markedly (departure time it follows, gap in minutes)
+pub fn headways(departure_times: &[i64]) -> (Option<i64>, Option<(i64, i64)>) {
+    let mut times = departure_times.to_vec();
+    times.sort();
+    times.dedup();
+
+    let gaps: Vec<(usize, i64)> = times
+        .windows(2)
+        .enumerate()
+        .map(|(index, pair)| (index, pair[1] - pair[0]))
+        .filter(|(_, gap)| *gap >= MIN_GAP_SECONDS)
+        .collect();
+
+    let mut current_gaps: Vec<i64> = gaps.iter().take(CURRENT_GAPS).map(|(_, gap)| *gap).collect();
+    let Some(current) = median(&mut current_gaps) else {
+        return (None, None);
+    };
+
+    // A change needs to be at least 5 minutes and half the current headway to be worth showing
+    let threshold = (current / 2).max(5 * 60);
+    let change = gaps
+        .iter()
+        .skip(1)
+        .find(|(_, gap)| (gap - current).abs() >= threshold)
+        .map(|(index, gap)| (times[*index], (gap + 30) / 60));
+
+    (Some((current + 30) / 60), change)
+}
+
+pub fn frequency_text(current_headway_minutes: Option<i64>, upcoming: usize, last_train: Option<&LastTrain>) -> String {
+    match (current_headway_minutes, last_train) {
+        (_, Some(last_train)) if upcoming <= 1 => format!("Last train at {}", last_train.display_time),
+        (Some(minutes), _) => format!("Trains every {} min", minutes),
+        (None, _) if upcoming == 1 => "One upcoming train".to_string(),
+        _ => "No upcoming trains".to_string(),
+    }
+}
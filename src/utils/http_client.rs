@@ -0,0 +1,23 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// reqwest clients keep a connection pool, so share one per timeout instead of
+// building a new client for every upstream request
+lazy_static::lazy_static! {
+    static ref CLIENTS: Mutex<HashMap<u64, reqwest::Client>> = Mutex::new(HashMap::new());
+}
+
+// Client whose requests give up after `timeout_ms` milliseconds
+pub fn client(timeout_ms: u64) -> reqwest::Client {
+    let mut clients = CLIENTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    clients
+        .entry(timeout_ms)
+        .or_insert_with(|| {
+            reqwest::Client::builder()
+                .timeout(Duration::from_millis(timeout_ms))
+                .build()
+                .unwrap_or_default()
+        })
+        .clone()
+}
@@ -0,0 +1,53 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaveState {
+    LeaveNow,
+    LeaveIn,
+    TooLate,
+}
+
+// When to head out to catch a train, given how long it takes to get to the station
+#[derive(Serialize, Clone, Debug)]
+pub struct LeaveBy {
+    pub state: LeaveState,
+    // Latest unix time to leave and still make the train
+    pub leave_by_time: i64,
+    pub display_time: String,
+    // Whole minutes until leave_by_time, negative once it has passed
+    pub minutes_until: i64,
+    // e.g. "Leave now", "Leave in 4 min", "Too late"
+    pub label: String,
+}
+
+impl LeaveBy {
+    pub fn for_departure(departure_time: i64, walk_minutes: u32, now: i64, tz: &Tz) -> Self {
+        let leave_by_time = departure_time - walk_minutes as i64 * 60;
+        let seconds_until = leave_by_time - now;
+        // Round down: leaving at "4 min" when there are 4:50 left is still in time
+        let minutes_until = seconds_until.div_euclid(60);
+
+        let (state, label) = if seconds_until < 0 {
+            (LeaveState::TooLate, "Too late".to_string())
+        } else if minutes_until == 0 {
+            (LeaveState::LeaveNow, "Leave now".to_string())
+        } else {
+            (LeaveState::LeaveIn, format!("Leave in {} min", minutes_until))
+        };
+
+        LeaveBy {
+            state,
+            leave_by_time,
+            display_time: DateTime::from_timestamp(leave_by_time, 0)
+                .unwrap_or_default()
+                .with_timezone(tz)
+                .format("%-I:%M %p")
+                .to_string(),
+            minutes_until,
+            label,
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use serde::Deserialize;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    // Human readable lines
+    #[default]
+    Text,
+    // One JSON object per event, for log collectors
+    Json,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    // tracing filter directive, e.g. "info" or "info,trmnl_plugin_server=debug". RUST_LOG wins when set.
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: LogFormat::Text,
+            level: "info".to_string(),
+        }
+    }
+}
+
+// The subscriber for a logging config, writing to `writer` (stdout for the server)
+pub fn subscriber<W>(config: &LogConfig, writer: W) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.level));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer);
+    match config.format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().flatten_event(true).with_current_span(true).finish()),
+    }
+}
+
+// Install the global subscriber. Only the first call takes effect, so tests can call it freely.
+pub fn init(config: &LogConfig) {
+    let _ = subscriber(config, std::io::stdout).try_init();
+}
@@ -0,0 +1,469 @@
+use crate::config::{AppConfig, MbtaConfig};
+use crate::error::AppError;
+use chrono::DateTime;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// Longest we'll wait out a rate limit before giving up on the request
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+// Typed client for the MBTA V3 API (https://api-v3.mbta.com/docs/swagger). Every endpoint
+// answers in JSON:API form: `data` resources whose `relationships` point at other resources,
+// which are sent along in `included` when asked for with `include=`.
+#[derive(Clone)]
+pub struct MbtaClient {
+    http: reqwest::Client,
+    config: MbtaConfig,
+}
+
+// One endpoint's resources together with everything the request asked to include
+#[derive(Debug, Default)]
+pub struct MbtaResponse<T> {
+    pub data: Vec<T>,
+    pub included: Included,
+}
+
+// Included resources by id, for resolving relationships
+#[derive(Debug, Default)]
+pub struct Included {
+    pub stops: HashMap<String, Stop>,
+    pub routes: HashMap<String, Route>,
+    pub trips: HashMap<String, Trip>,
+    pub vehicles: HashMap<String, Vehicle>,
+}
+
+// A resource as it comes off the wire, before its attributes are typed
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Resource {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub attributes: Value,
+    pub relationships: HashMap<String, Relationship>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Relationship {
+    // A single related resource; to-many relationships aren't used by any of our types
+    #[serde(deserialize_with = "single_relation")]
+    pub data: Option<ResourceId>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ResourceId {
+    pub id: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Document {
+    data: Vec<Resource>,
+    included: Vec<Resource>,
+}
+
+impl Resource {
+    pub fn related(&self, relationship: &str) -> Option<String> {
+        self.relationships
+            .get(relationship)?
+            .data
+            .as_ref()
+            .map(|data| data.id.clone())
+    }
+}
+
+fn single_relation<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ResourceId>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Object(object) => serde_json::from_value(Value::Object(object)).ok(),
+        _ => None,
+    })
+}
+
+// ISO 8601 timestamps ("2025-03-04T11:02:00-05:00") as unix seconds
+fn timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    let text: Option<String> = Option::deserialize(deserializer)?;
+    Ok(text
+        .and_then(|text| DateTime::parse_from_rfc3339(&text).ok())
+        .map(|time| time.timestamp()))
+}
+
+// A typed resource: attributes deserialized straight from `attributes`, ids and
+// relationships filled in from the rest of the resource
+pub trait FromResource: DeserializeOwned {
+    const KIND: &'static str;
+    fn link(&mut self, resource: &Resource);
+
+    fn from_resource(resource: &Resource) -> Result<Self, serde_json::Error> {
+        let attributes = match &resource.attributes {
+            Value::Null => Value::Object(Default::default()),
+            attributes => attributes.clone(),
+        };
+        let mut typed: Self = serde_json::from_value(attributes)?;
+        typed.link(resource);
+        Ok(typed)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Stop {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    pub name: String,
+    // 0 = platform/stop, 1 = station, 2 = entrance
+    pub location_type: u8,
+    pub platform_code: Option<String>,
+    pub platform_name: Option<String>,
+    pub vehicle_type: Option<u8>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub wheelchair_boarding: u8,
+    #[serde(skip_deserializing)]
+    pub parent_station: Option<String>,
+}
+
+impl FromResource for Stop {
+    const KIND: &'static str = "stop";
+    fn link(&mut self, resource: &Resource) {
+        self.id = resource.id.clone();
+        self.parent_station = resource.related("parent_station");
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Route {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    pub short_name: String,
+    pub long_name: String,
+    // GTFS route_type: 0 = light rail, 1 = subway, 2 = commuter rail, 3 = bus, 4 = ferry
+    #[serde(rename = "type")]
+    pub route_type: u8,
+    pub color: String,
+    pub direction_names: Vec<String>,
+    pub direction_destinations: Vec<String>,
+}
+
+impl Route {
+    // "Red Line", "Green Line B", "Providence/Stoughton Line", "1"
+    pub fn name(&self) -> &str {
+        if self.long_name.is_empty() || (self.route_type == 3 && !self.short_name.is_empty()) {
+            &self.short_name
+        } else {
+            &self.long_name
+        }
+    }
+}
+
+impl FromResource for Route {
+    const KIND: &'static str = "route";
+    fn link(&mut self, resource: &Resource) {
+        self.id = resource.id.clone();
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Trip {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    pub headsign: String,
+    // Commuter rail train number, e.g. "801"
+    pub name: String,
+    pub direction_id: Option<u8>,
+    // GTFS values: 0 = no information, 1 = yes, 2 = no
+    pub wheelchair_accessible: u8,
+    pub bikes_allowed: u8,
+    #[serde(skip_deserializing)]
+    pub route_id: Option<String>,
+}
+
+impl FromResource for Trip {
+    const KIND: &'static str = "trip";
+    fn link(&mut self, resource: &Resource) {
+        self.id = resource.id.clone();
+        self.route_id = resource.related("route");
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Prediction {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    #[serde(deserialize_with = "timestamp")]
+    pub arrival_time: Option<i64>,
+    #[serde(deserialize_with = "timestamp")]
+    pub departure_time: Option<i64>,
+    pub direction_id: Option<u8>,
+    // Free text such as "Boarding", "Approaching" or commuter rail's "On time"/"Delayed"
+    pub status: Option<String>,
+    pub stop_sequence: Option<u32>,
+    // "ADDED", "CANCELLED", "SKIPPED", "UNSCHEDULED" or null for a normal stop
+    pub schedule_relationship: Option<String>,
+    #[serde(skip_deserializing)]
+    pub route_id: Option<String>,
+    #[serde(skip_deserializing)]
+    pub trip_id: Option<String>,
+    #[serde(skip_deserializing)]
+    pub stop_id: Option<String>,
+    #[serde(skip_deserializing)]
+    pub vehicle_id: Option<String>,
+}
+
+impl FromResource for Prediction {
+    const KIND: &'static str = "prediction";
+    fn link(&mut self, resource: &Resource) {
+        self.id = resource.id.clone();
+        self.route_id = resource.related("route");
+        self.trip_id = resource.related("trip");
+        self.stop_id = resource.related("stop");
+        self.vehicle_id = resource.related("vehicle");
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Schedule {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    #[serde(deserialize_with = "timestamp")]
+    pub arrival_time: Option<i64>,
+    #[serde(deserialize_with = "timestamp")]
+    pub departure_time: Option<i64>,
+    pub direction_id: Option<u8>,
+    pub stop_sequence: Option<u32>,
+    #[serde(skip_deserializing)]
+    pub route_id: Option<String>,
+    #[serde(skip_deserializing)]
+    pub trip_id: Option<String>,
+    #[serde(skip_deserializing)]
+    pub stop_id: Option<String>,
+}
+
+impl FromResource for Schedule {
+    const KIND: &'static str = "schedule";
+    fn link(&mut self, resource: &Resource) {
+        self.id = resource.id.clone();
+        self.route_id = resource.related("route");
+        self.trip_id = resource.related("trip");
+        self.stop_id = resource.related("stop");
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Alert {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    pub header: String,
+    pub short_header: String,
+    pub description: Option<String>,
+    // e.g. "DELAY", "SHUTTLE", "SUSPENSION", "ELEVATOR_CLOSURE"
+    pub effect: String,
+    pub cause: String,
+    // 0 (least) to 10 (most severe)
+    pub severity: u8,
+    // "NEW", "ONGOING", "UPCOMING", ...
+    pub lifecycle: String,
+    pub active_period: Vec<ActivePeriod>,
+    pub informed_entity: Vec<InformedEntity>,
+    pub url: Option<String>,
+    #[serde(deserialize_with = "timestamp")]
+    pub updated_at: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ActivePeriod {
+    #[serde(deserialize_with = "timestamp")]
+    pub start: Option<i64>,
+    #[serde(deserialize_with = "timestamp")]
+    pub end: Option<i64>,
+}
+
+// Who an alert applies to; every field that is set has to match
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct InformedEntity {
+    pub route: Option<String>,
+    pub route_type: Option<u8>,
+    pub stop: Option<String>,
+    pub trip: Option<String>,
+    pub direction_id: Option<u8>,
+    pub facility: Option<String>,
+    // e.g. "BOARD", "EXIT", "RIDE", "USING_WHEELCHAIR"
+    pub activities: Vec<String>,
+}
+
+impl FromResource for Alert {
+    const KIND: &'static str = "alert";
+    fn link(&mut self, resource: &Resource) {
+        self.id = resource.id.clone();
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Vehicle {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    pub label: String,
+    // "INCOMING_AT", "STOPPED_AT" or "IN_TRANSIT_TO", relative to `stop_id`
+    pub current_status: String,
+    pub current_stop_sequence: Option<u32>,
+    pub direction_id: Option<u8>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub bearing: Option<f64>,
+    pub speed: Option<f64>,
+    #[serde(deserialize_with = "timestamp")]
+    pub updated_at: Option<i64>,
+    #[serde(skip_deserializing)]
+    pub route_id: Option<String>,
+    #[serde(skip_deserializing)]
+    pub trip_id: Option<String>,
+    #[serde(skip_deserializing)]
+    pub stop_id: Option<String>,
+}
+
+impl FromResource for Vehicle {
+    const KIND: &'static str = "vehicle";
+    fn link(&mut self, resource: &Resource) {
+        self.id = resource.id.clone();
+        self.route_id = resource.related("route");
+        self.trip_id = resource.related("trip");
+        self.stop_id = resource.related("stop");
+    }
+}
+
+fn decode<T: FromResource>(resource: &Resource) -> Result<T, AppError> {
+    T::from_resource(resource)
+        .map_err(|e| AppError::DecodeFailed(format!("Failed to decode MBTA {} {}: {}", T::KIND, resource.id, e)))
+}
+
+impl Included {
+    fn from_resources(resources: &[Resource]) -> Result<Self, AppError> {
+        let mut included = Included::default();
+        for resource in resources {
+            match resource.kind.as_str() {
+                Stop::KIND => {
+                    included.stops.insert(resource.id.clone(), decode(resource)?);
+                }
+                Route::KIND => {
+                    included.routes.insert(resource.id.clone(), decode(resource)?);
+                }
+                Trip::KIND => {
+                    included.trips.insert(resource.id.clone(), decode(resource)?);
+                }
+                Vehicle::KIND => {
+                    included.vehicles.insert(resource.id.clone(), decode(resource)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(included)
+    }
+}
+
+// How long the API wants us to wait: `x-ratelimit-reset` is the unix time the window
+// reopens, otherwise back off exponentially
+fn backoff(response: &reqwest::Response, attempt: u32) -> Duration {
+    let reset = response
+        .headers()
+        .get("x-ratelimit-reset")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i64>().ok())
+        .map(|reset| Duration::from_secs((reset - chrono::Utc::now().timestamp()).max(0) as u64));
+    reset
+        .unwrap_or_else(|| Duration::from_millis(250 * 2u64.pow(attempt)))
+        .min(MAX_BACKOFF)
+}
+
+impl MbtaClient {
+    pub fn new(config: &AppConfig) -> Self {
+        MbtaClient {
+            http: config.http_client(),
+            config: config.mbta.clone(),
+        }
+    }
+
+    // GET one endpoint, e.g. `get("/predictions", &[("filter[stop]", "place-sstat")])`
+    pub async fn get<T: FromResource>(&self, path: &str, query: &[(&str, &str)]) -> Result<MbtaResponse<T>, AppError> {
+        let url = format!("{}{}", self.config.base_url.trim_end_matches('/'), path);
+        let started = Instant::now();
+        let mut attempt = 0;
+
+        let response = loop {
+            let mut request = self.http.get(&url).query(query);
+            if let Some(api_key) = &self.config.api_key {
+                request = request.header("x-api-key", api_key);
+            }
+            let response = request
+                .send()
+                .await
+                .map_err(|e| AppError::UpstreamUnavailable(format!("Failed to fetch MBTA {}: {}", path, e)))?;
+
+            if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                break response
+                    .error_for_status()
+                    .map_err(|e| AppError::UpstreamUnavailable(format!("Failed to fetch MBTA {}: {}", path, e)))?;
+            }
+            if attempt >= self.config.rate_limit_retries {
+                return Err(AppError::UpstreamUnavailable(format!("MBTA {} is rate limited", path)));
+            }
+            let wait = backoff(&response, attempt);
+            warn!(path, attempt, wait_ms = wait.as_millis() as u64, "MBTA API rate limited, backing off");
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        };
+
+        let document: Document = response
+            .json()
+            .await
+            .map_err(|e| AppError::DecodeFailed(format!("Failed to decode MBTA {}: {}", path, e)))?;
+        info!(
+            path,
+            latency_ms = started.elapsed().as_millis() as u64,
+            resources = document.data.len(),
+            included = document.included.len(),
+            "fetched MBTA API"
+        );
+
+        Ok(MbtaResponse {
+            data: document.data.iter().map(decode).collect::<Result<_, _>>()?,
+            included: Included::from_resources(&document.included)?,
+        })
+    }
+
+    pub async fn stops(&self, query: &[(&str, &str)]) -> Result<MbtaResponse<Stop>, AppError> {
+        self.get("/stops", query).await
+    }
+
+    pub async fn routes(&self, query: &[(&str, &str)]) -> Result<MbtaResponse<Route>, AppError> {
+        self.get("/routes", query).await
+    }
+
+    pub async fn predictions(&self, query: &[(&str, &str)]) -> Result<MbtaResponse<Prediction>, AppError> {
+        self.get("/predictions", query).await
+    }
+
+    pub async fn schedules(&self, query: &[(&str, &str)]) -> Result<MbtaResponse<Schedule>, AppError> {
+        self.get("/schedules", query).await
+    }
+
+    pub async fn alerts(&self, query: &[(&str, &str)]) -> Result<MbtaResponse<Alert>, AppError> {
+        self.get("/alerts", query).await
+    }
+
+    pub async fn vehicles(&self, query: &[(&str, &str)]) -> Result<MbtaResponse<Vehicle>, AppError> {
+        self.get("/vehicles", query).await
+    }
+}
@@ -0,0 +1,214 @@
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::utils::csv_reader;
+use crate::utils::gtfs_helper::{self, normalize_name, GtfsIndex, GtfsStop};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+type LoadResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// An MBTA station with everything needed to resolve it offline
+#[derive(Clone, Debug)]
+pub struct MbtaStation {
+    // Parent stop id, e.g. "place-sstat"
+    pub id: String,
+    pub name: String,
+    // Child platform stops, e.g. South Station's Red Line platforms and commuter rail stop
+    pub platforms: Vec<GtfsStop>,
+    // Route ids serving the station, e.g. ["CR-Fairmount", ..., "Red"]
+    pub routes: Vec<String>,
+    pub aliases: Vec<String>,
+}
+
+impl MbtaStation {
+    pub fn serves(&self, route_id: &str) -> bool {
+        self.routes.iter().any(|route| route == route_id)
+    }
+}
+
+// Parent stations of one MBTA GTFS feed, looked up by id, name, alias or platform id
+pub struct StationIndex {
+    pub gtfs: Arc<GtfsIndex>,
+    pub stations: HashMap<String, MbtaStation>,
+    // Stops with no parent station (mostly bus stops), only looked up by id
+    standalone: HashMap<String, MbtaStation>,
+    // normalized alias -> parent station id
+    aliases: HashMap<String, String>,
+}
+
+impl StationIndex {
+    // `station_routes` and `aliases` are (stop_id, route_id) and (alias, stop_id) pairs from
+    // station_routes.txt and station_aliases.txt. Route membership also comes from
+    // stop_times.txt when the feed has it.
+    pub fn build(gtfs: Arc<GtfsIndex>, station_routes: &[(String, String)], aliases: &[(String, String)]) -> Self {
+        let mut routes: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for (stop_id, route_id) in station_routes {
+            routes.entry(gtfs.parent_station_id(stop_id)).or_default().insert(route_id.clone());
+        }
+        for (station_id, stops) in gtfs.stop_times.iter() {
+            for stop in stops {
+                if let Some(trip) = gtfs.trips.get(&stop.trip_id) {
+                    routes.entry(station_id.clone()).or_default().insert(trip.route_id.clone());
+                }
+            }
+        }
+
+        let mut stations: HashMap<String, MbtaStation> = gtfs
+            .stops
+            .values()
+            .filter(|stop| stop.location_type == 1)
+            .map(|stop| {
+                let station = MbtaStation {
+                    id: stop.stop_id.clone(),
+                    name: stop.stop_name.clone(),
+                    platforms: Vec::new(),
+                    routes: routes.remove(&stop.stop_id).unwrap_or_default().into_iter().collect(),
+                    aliases: Vec::new(),
+                };
+                (station.id.clone(), station)
+            })
+            .collect();
+
+        let mut standalone = HashMap::new();
+        for stop in gtfs.stops.values().filter(|stop| stop.location_type == 0) {
+            match &stop.parent_station {
+                Some(parent) => {
+                    if let Some(station) = stations.get_mut(parent) {
+                        station.platforms.push(stop.clone());
+                    }
+                }
+                None => {
+                    let station = MbtaStation {
+                        id: stop.stop_id.clone(),
+                        name: stop.stop_name.clone(),
+                        platforms: Vec::new(),
+                        routes: routes.remove(&stop.stop_id).unwrap_or_default().into_iter().collect(),
+                        aliases: Vec::new(),
+                    };
+                    standalone.insert(station.id.clone(), station);
+                }
+            }
+        }
+        for station in stations.values_mut() {
+            station.platforms.sort_by(|a, b| a.stop_id.cmp(&b.stop_id));
+        }
+
+        let mut alias_index = HashMap::new();
+        for (alias, stop_id) in aliases {
+            let station_id = gtfs.parent_station_id(stop_id);
+            if let Some(station) = stations.get_mut(&station_id) {
+                station.aliases.push(alias.clone());
+                alias_index.insert(normalize_name(alias), station_id);
+            }
+        }
+
+        StationIndex {
+            gtfs,
+            stations,
+            standalone,
+            aliases: alias_index,
+        }
+    }
+
+    // Station by stop id (parent or platform), exact name, alias, then a name the query is the
+    // start of. A prefix shared by several stations ("Newton") finds nothing rather than
+    // guessing, and a longer query never falls back to a shorter name ("Harvard Ave" is not
+    // "Harvard").
+    pub fn find(&self, query: &str) -> Option<&MbtaStation> {
+        let query = query.trim();
+        if let Some(station) = self.station_for_stop(query) {
+            return Some(station);
+        }
+
+        let wanted = normalize_name(query);
+        if let Some(station) = self.stations.values().find(|station| normalize_name(&station.name) == wanted) {
+            return Some(station);
+        }
+        if let Some(station) = self.aliases.get(&wanted).and_then(|id| self.stations.get(id)) {
+            return Some(station);
+        }
+
+        if wanted.is_empty() {
+            return None;
+        }
+        let mut prefixed = self
+            .stations
+            .values()
+            .filter(|station| normalize_name(&station.name).starts_with(&wanted));
+        match (prefixed.next(), prefixed.next()) {
+            (Some(station), None) => Some(station),
+            _ => None,
+        }
+    }
+
+    // The station a stop id belongs to, e.g. "70079" -> South Station, or a bus stop itself
+    pub fn station_for_stop(&self, stop_id: &str) -> Option<&MbtaStation> {
+        let stop_id = match self.gtfs.stops.get_key_value(stop_id) {
+            Some((stop_id, _)) => stop_id,
+            None => self.gtfs.stops.keys().find(|id| id.eq_ignore_ascii_case(stop_id))?,
+        };
+        self.stations
+            .get(&self.gtfs.parent_station_id(stop_id))
+            .or_else(|| self.standalone.get(stop_id))
+    }
+}
+
+fn pairs(rows: Vec<HashMap<String, String>>, first: &str, second: &str) -> Vec<(String, String)> {
+    rows.into_iter()
+        .filter_map(|mut row| Some((row.remove(first)?, row.remove(second)?)))
+        .collect()
+}
+
+fn embedded_pairs(file: &str, first: &str, second: &str) -> Vec<(String, String)> {
+    csv_reader::read_embedded_csv(&format!("mbta_gtfs/{}", file))
+        .map(|rows| pairs(rows, first, second))
+        .unwrap_or_default()
+}
+
+// Station index over a full GTFS directory. Aliases come from the directory's own
+// station_aliases.txt when it has one, otherwise from the embedded list.
+fn load_dir(gtfs: Arc<GtfsIndex>, dir: &Path) -> LoadResult<StationIndex> {
+    let optional = |file: &str, first: &str, second: &str| -> LoadResult<Option<Vec<(String, String)>>> {
+        let path = dir.join(file);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(pairs(csv_reader::read_csv_file(&path)?, first, second)))
+    };
+    let station_routes = optional("station_routes.txt", "stop_id", "route_id")?.unwrap_or_default();
+    let aliases = optional("station_aliases.txt", "alias", "stop_id")?
+        .unwrap_or_else(|| embedded_pairs("station_aliases.txt", "alias", "stop_id"));
+    Ok(StationIndex::build(gtfs, &station_routes, &aliases))
+}
+
+lazy_static::lazy_static! {
+    // Rapid transit stations and commuter rail hubs, without schedules
+    pub static ref MBTA_GTFS: Arc<GtfsIndex> =
+        Arc::new(GtfsIndex::load_embedded("mbta_gtfs").expect("embedded MBTA GTFS data should parse"));
+    pub static ref MBTA_STATIONS: Arc<StationIndex> = Arc::new(StationIndex::build(
+        MBTA_GTFS.clone(),
+        &embedded_pairs("station_routes.txt", "stop_id", "route_id"),
+        &embedded_pairs("station_aliases.txt", "alias", "stop_id"),
+    ));
+    // Station indexes over GTFS directories on disk, keyed by path
+    static ref DISK_STATIONS: Mutex<HashMap<String, Arc<StationIndex>>> = Mutex::new(HashMap::new());
+}
+
+// The station index for the configured feed: `mbta.gtfs_dir` when set, else the embedded extract
+pub async fn station_index(config: &AppConfig) -> Result<Arc<StationIndex>, AppError> {
+    let Some(dir) = &config.mbta.gtfs_dir else {
+        return Ok(MBTA_STATIONS.clone());
+    };
+    if let Some(index) = DISK_STATIONS.lock().unwrap().get(dir) {
+        return Ok(index.clone());
+    }
+
+    let unreadable = |e: Box<dyn std::error::Error + Send + Sync>| {
+        AppError::UpstreamUnavailable(format!("Failed to load MBTA GTFS from {}: {}", dir, e))
+    };
+    let gtfs = gtfs_helper::load_dir_cached(dir).await.map_err(unreadable)?;
+    let index = Arc::new(load_dir(gtfs, Path::new(dir)).map_err(unreadable)?);
+    DISK_STATIONS.lock().unwrap().insert(dir.clone(), index.clone());
+    Ok(index)
+}
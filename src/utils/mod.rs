@@ -1,2 +1,13 @@
+pub mod bart_etd;
 pub mod csv_reader;
-pub mod gtfs_helper;
\ No newline at end of file
+pub mod feed_source;
+pub mod gtfs_helper;
+pub mod headway;
+pub mod http_client;
+pub mod leave_by;
+pub mod logging;
+pub mod mbta_api;
+pub mod mbta_stations;
+pub mod refresh;
+pub mod train_status;
+pub mod transit;
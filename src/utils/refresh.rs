@@ -0,0 +1,70 @@
+use actix_web::http::header::{CacheControl, CacheDirective, Expires};
+use actix_web::HttpResponse;
+use chrono::{DateTime, Days, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::time::{Duration, UNIX_EPOCH};
+
+// Never ask a device to come back sooner than the feed cache turns over, or later than this
+const MIN_REFRESH_SECONDS: i64 = 30;
+const MAX_REFRESH_SECONDS: i64 = 24 * 60 * 60;
+
+// When a device should next poll, included in every plugin response and mirrored in the
+// Cache-Control/Expires headers
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RefreshHint {
+    pub refresh_at: i64,
+    pub refresh_in_seconds: i64,
+}
+
+impl RefreshHint {
+    pub fn at(refresh_at: i64, now: DateTime<Utc>) -> Self {
+        let refresh_in_seconds = (refresh_at - now.timestamp()).clamp(MIN_REFRESH_SECONDS, MAX_REFRESH_SECONDS);
+        RefreshHint {
+            refresh_at: now.timestamp() + refresh_in_seconds,
+            refresh_in_seconds,
+        }
+    }
+
+    pub fn after(seconds: i64, now: DateTime<Utc>) -> Self {
+        Self::at(now.timestamp() + seconds, now)
+    }
+
+    // Start of the next day in `tz`, for content that changes once a day. Days around a DST
+    // change are 23 or 25 hours long; where a change skips midnight itself, the day starts
+    // at the first hour that exists.
+    pub fn next_midnight(now: DateTime<Utc>, tz: &Tz) -> Self {
+        let midnight = now
+            .with_timezone(tz)
+            .date_naive()
+            .checked_add_days(Days::new(1))
+            .and_then(|date| {
+                (0..3).find_map(|hour| {
+                    let start = date.and_hms_opt(hour, 0, 0)?;
+                    tz.from_local_datetime(&start).earliest()
+                })
+            })
+            .map(|midnight| midnight.timestamp())
+            .unwrap_or(now.timestamp() + MAX_REFRESH_SECONDS);
+        Self::at(midnight, now)
+    }
+
+    // The earliest of several hints, e.g. across the sections of a combined board
+    pub fn earliest(hints: impl IntoIterator<Item = RefreshHint>, now: DateTime<Utc>) -> Self {
+        hints
+            .into_iter()
+            .min_by_key(|hint| hint.refresh_at)
+            .unwrap_or_else(|| Self::after(MIN_REFRESH_SECONDS, now))
+    }
+
+    // 200 JSON response carrying the caching headers for this hint
+    pub fn json_response<T: Serialize>(&self, body: &T) -> HttpResponse {
+        HttpResponse::Ok()
+            .insert_header(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(self.refresh_in_seconds as u32),
+            ]))
+            .insert_header(Expires((UNIX_EPOCH + Duration::from_secs(self.refresh_at.max(0) as u64)).into()))
+            .json(body)
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+// Whether a train is running ahead of, on, or behind its schedule
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DelayState {
+    OnTime,
+    Early,
+    Late,
+    Unknown,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum DelaySeverity {
+    None,
+    Minor,
+    Moderate,
+    Severe,
+}
+
+// Per-agency cut-offs (in seconds) used to classify a train's deviation from its schedule
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DelayThresholds {
+    // Trains up to this many seconds early still count as on time
+    pub early_grace_seconds: i32,
+    // Trains up to this many seconds late still count as on time
+    pub late_grace_seconds: i32,
+    // Deviations of at least this many seconds are moderate
+    pub moderate_seconds: i32,
+    // Deviations of at least this many seconds are severe
+    pub severe_seconds: i32,
+}
+
+impl Default for DelayThresholds {
+    fn default() -> Self {
+        DelayThresholds {
+            early_grace_seconds: 30,
+            late_grace_seconds: 60,
+            moderate_seconds: 5 * 60,
+            severe_seconds: 10 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TrainStatus {
+    pub state: DelayState,
+    // Positive when late, negative when early
+    pub delay_seconds: Option<i32>,
+    pub delay_minutes: Option<i32>,
+    pub severity: DelaySeverity,
+    // Short text for the display, e.g. "On time", "2 min early", "7 min late"
+    pub label: String,
+}
+
+// Round seconds to whole minutes, never reporting "0 min" for a train that is off schedule
+fn whole_minutes(seconds: i32) -> i32 {
+    ((seconds.abs() + 30) / 60).max(1)
+}
+
+impl TrainStatus {
+    // Status from predicted and scheduled unix times
+    pub fn from_times(predicted: i64, scheduled: Option<i64>, thresholds: &DelayThresholds) -> Self {
+        let delay = scheduled.map(|scheduled| (predicted - scheduled) as i32);
+        Self::from_delay(delay, thresholds)
+    }
+
+    // Status for a departure only known from the static schedule
+    pub fn no_live_data() -> Self {
+        TrainStatus {
+            state: DelayState::Unknown,
+            delay_seconds: None,
+            delay_minutes: None,
+            severity: DelaySeverity::None,
+            label: "No live data".to_string(),
+        }
+    }
+
+    // Status from a delay in seconds (positive = late) as reported by GTFS-RT
+    pub fn from_delay(delay_seconds: Option<i32>, thresholds: &DelayThresholds) -> Self {
+        let Some(delay) = delay_seconds else {
+            return TrainStatus {
+                state: DelayState::Unknown,
+                delay_seconds: None,
+                delay_minutes: None,
+                severity: DelaySeverity::None,
+                label: "No delay info".to_string(),
+            };
+        };
+
+        let state = if delay > thresholds.late_grace_seconds {
+            DelayState::Late
+        } else if delay < -thresholds.early_grace_seconds {
+            DelayState::Early
+        } else {
+            DelayState::OnTime
+        };
+
+        let severity = match state {
+            DelayState::OnTime | DelayState::Unknown => DelaySeverity::None,
+            _ if delay.abs() >= thresholds.severe_seconds => DelaySeverity::Severe,
+            _ if delay.abs() >= thresholds.moderate_seconds => DelaySeverity::Moderate,
+            _ => DelaySeverity::Minor,
+        };
+
+        let (delay_minutes, label) = match state {
+            DelayState::Late => (whole_minutes(delay), format!("{} min late", whole_minutes(delay))),
+            DelayState::Early => (-whole_minutes(delay), format!("{} min early", whole_minutes(delay))),
+            _ => (0, "On time".to_string()),
+        };
+
+        TrainStatus {
+            state,
+            delay_seconds: Some(delay),
+            delay_minutes: Some(delay_minutes),
+            severity,
+            label,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Delays inside the grace window are on time, outside it the label and severity
+    // bucket follow the configured thresholds
+    #[test]
+    fn status_from_delay() {
+        let thresholds = DelayThresholds::default();
+
+        let on_time = TrainStatus::from_delay(Some(20), &thresholds);
+        assert_eq!(on_time.state, DelayState::OnTime);
+        assert_eq!(on_time.severity, DelaySeverity::None);
+        assert_eq!(on_time.label, "On time");
+
+        // Early trains used to be reported as "on time"
+        let early = TrainStatus::from_delay(Some(-95), &thresholds);
+        assert_eq!(early.state, DelayState::Early);
+        assert_eq!(early.delay_minutes, Some(-2));
+        assert_eq!(early.label, "2 min early");
+
+        let late = TrainStatus::from_times(1_000 + 93, Some(1_000), &thresholds);
+        assert_eq!(late.state, DelayState::Late);
+        assert_eq!(late.severity, DelaySeverity::Minor);
+        assert_eq!(late.label, "2 min late");
+
+        let severe = TrainStatus::from_delay(Some(12 * 60), &thresholds);
+        assert_eq!(severe.severity, DelaySeverity::Severe);
+
+        let unknown = TrainStatus::from_times(1_000, None, &thresholds);
+        assert_eq!(unknown.state, DelayState::Unknown);
+
+        // Thresholds are per agency, a stricter agency reports the same delay as late
+        let strict = DelayThresholds {
+            late_grace_seconds: 0,
+            ..DelayThresholds::default()
+        };
+        assert_eq!(TrainStatus::from_delay(Some(20), &strict).state, DelayState::Late);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::utils::refresh::RefreshHint;
+use chrono_tz::Tz;
+use gtfs_realtime::alert::{Effect, SeverityLevel};
+use gtfs_realtime::{EntitySelector, FeedMessage, TranslatedString};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+// One transit agency's data source and station index. The board and alerts handlers in
+// handlers::transit are written once against this; an agency supplies how to find its
+// stations, build a station board and read its alerts.
+//
+// Only ever used through generics (the server runs handlers on one thread), so the
+// futures don't need to be Send.
+#[allow(async_fn_in_trait)]
+pub trait TransitProvider {
+    // Board request body, e.g. {"station": "Walnut Creek", "line": "Yellow", ...}
+    type Request: DeserializeOwned + Serialize;
+    type Station;
+    type Board: StationBoard;
+
+    // Short agency name, e.g. "BART"
+    fn agency(&self) -> &str;
+    fn timezone(&self) -> Tz;
+    // The agency's board request for an agency-neutral one, e.g. a leg of a commute
+    fn board_request(query: &BoardQuery) -> Self::Request;
+    // The station name or id a board request asks for
+    fn station_query(request: &Self::Request) -> &str;
+    fn station_name(station: &Self::Station) -> String;
+    async fn resolve_station(&self, query: &str) -> Result<Self::Station, AppError>;
+    // Upcoming departures from the station, as the agency's board
+    async fn predictions(&self, station: &Self::Station, request: &Self::Request) -> Result<Self::Board, AppError>;
+    // Alerts for a station, a route, or both, in no particular order
+    async fn alerts(&self, station: Option<&Self::Station>, route: Option<&str>) -> Result<Vec<TransitAlert>, AppError>;
+}
+
+// Providers whose settings all live in AppConfig, so the handlers can build them per request
+pub trait FromAppConfig {
+    fn from_config(config: &AppConfig) -> Self;
+}
+
+// A station board asked for without knowing the agency's request format
+pub struct BoardQuery<'a> {
+    pub station: &'a str,
+    // Line or route filter, in whatever form the agency's board takes it
+    pub line: Option<&'a str>,
+    // GTFS direction_id, None for both
+    pub direction: Option<u8>,
+    pub show_time_not_minutes: bool,
+}
+
+// Board request body for agencies configured under `agencies`, and what BART's own request
+// body is read into, e.g. {"station": "San Francisco", "line": "Local", "direction": 0}
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TransitRequest {
+    pub station: String,
+    // Route id, short name or long name; every line when missing
+    #[serde(default)]
+    pub line: Option<String>,
+    // GTFS direction_id; both directions when missing
+    #[serde(default)]
+    pub direction: Option<u8>,
+    // Clock times ("4:05 PM") in the agency's timezone instead of minutes
+    #[serde(default)]
+    pub show_time_not_minutes: bool,
+    // How long it takes to get to the station, used for the per-train leave-by time
+    #[serde(default)]
+    pub walk_minutes: u32,
+    // Drop trips that aren't known to be wheelchair accessible
+    #[serde(default)]
+    pub accessible_only: bool,
+}
+
+impl TransitRequest {
+    pub fn wants_direction(&self, direction_id: Option<u8>) -> bool {
+        self.direction.is_none_or(|wanted| direction_id == Some(wanted))
+    }
+}
+
+impl From<&BoardQuery<'_>> for TransitRequest {
+    fn from(query: &BoardQuery) -> Self {
+        TransitRequest {
+            station: query.station.to_string(),
+            line: query.line.map(str::to_string),
+            direction: query.direction,
+            show_time_not_minutes: query.show_time_not_minutes,
+            ..TransitRequest::default()
+        }
+    }
+}
+
+// What every agency's board has in common
+pub trait StationBoard: Serialize {
+    fn departures(&self) -> Vec<Departure>;
+    fn refresh(&self) -> &RefreshHint;
+}
+
+// One upcoming departure in agency-neutral form
+#[derive(Serialize, Clone, Debug)]
+pub struct Departure {
+    pub line: String,
+    pub route_id: String,
+    pub destination: String,
+    pub direction_id: Option<u8>,
+    pub departure_time: i64,
+    pub minutes: i64,
+    pub display_time: String,
+    // The agency board's own line for it, e.g. "Yellow to Antioch - 5 min (On time)"
+    pub summary: String,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    // Delays, shuttles, suspensions, detours
+    Service,
+    // Elevator and escalator outages and other station access problems
+    Facility,
+}
+
+// A service alert in agency-neutral form
+#[derive(Serialize, Clone, Debug)]
+pub struct TransitAlert {
+    pub id: String,
+    pub kind: AlertKind,
+    // e.g. "DELAY" (MBTA) or "SIGNIFICANT_DELAYS" (GTFS-RT)
+    pub effect: String,
+    // 0 (least) to 10 (most severe), MBTA's scale
+    pub severity: u8,
+    // (start, end) pairs, either open ended; empty means in effect until taken down
+    pub active_period: Vec<(Option<i64>, Option<i64>)>,
+    pub header: String,
+    // Shorter header for small screens, where the agency writes one
+    pub short_header: Option<String>,
+}
+
+// Whether an alert's informed entity concerns what was asked about. Agency-wide entities
+// concern everyone; ones naming a stop only show for that station, and ones naming a route
+// only when no route or that route was asked for.
+pub fn informs(
+    entity: &EntitySelector,
+    at_station: Option<impl Fn(&str) -> bool>,
+    on_route: Option<impl Fn(&str) -> bool>,
+) -> bool {
+    let station_ok = match (&entity.stop_id, &at_station) {
+        (None, _) => true,
+        (Some(stop_id), Some(at_station)) => at_station(stop_id),
+        (Some(_), None) => false,
+    };
+    let route_ok = match (&entity.route_id, &on_route) {
+        (Some(route_id), Some(on_route)) => on_route(route_id),
+        _ => true,
+    };
+    station_ok && route_ok
+}
+
+// English text, else whatever the first translation is
+fn english(text: Option<&TranslatedString>) -> Option<String> {
+    let translations = &text?.translation;
+    translations
+        .iter()
+        .find(|translation| translation.language.as_deref().is_none_or(|language| language.starts_with("en")))
+        .or(translations.first())
+        .map(|translation| translation.text.clone())
+}
+
+// Alerts from a GTFS-RT alerts feed that inform an entity `wanted` accepts. GTFS-RT only
+// has four severity levels, spread over MBTA's 0-10 scale.
+pub fn alerts_from_feed(feed: &FeedMessage, wanted: impl Fn(&EntitySelector) -> bool) -> Vec<TransitAlert> {
+    feed.entity
+        .iter()
+        .filter(|entity| entity.is_deleted != Some(true))
+        .filter_map(|entity| Some((entity.id.clone(), entity.alert.as_ref()?)))
+        .filter(|(_, alert)| alert.informed_entity.iter().any(&wanted))
+        .map(|(id, alert)| {
+            let effect = alert.effect.and_then(|effect| Effect::try_from(effect).ok());
+            let severity = match alert.severity_level.and_then(|level| SeverityLevel::try_from(level).ok()) {
+                Some(SeverityLevel::Info) => 3,
+                Some(SeverityLevel::Warning) => 6,
+                Some(SeverityLevel::Severe) => 9,
+                _ => 5,
+            };
+            TransitAlert {
+                id,
+                kind: match effect {
+                    Some(Effect::AccessibilityIssue) => AlertKind::Facility,
+                    _ => AlertKind::Service,
+                },
+                effect: effect.unwrap_or(Effect::UnknownEffect).as_str_name().to_string(),
+                severity,
+                active_period: alert
+                    .active_period
+                    .iter()
+                    .map(|period| (period.start.map(|start| start as i64), period.end.map(|end| end as i64)))
+                    .collect(),
+                header: english(alert.header_text.as_ref()).unwrap_or_default(),
+                short_header: None,
+            }
+        })
+        .collect()
+}
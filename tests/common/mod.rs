@@ -0,0 +1,254 @@
+// Mock upstream harness shared by the integration tests.
+//
+// `MockUpstream::start()` runs a local HTTP server that answers every upstream the
+// plugins talk to (BART GTFS-RT and ETD, MBTA, ...) from fixtures registered with `serve`,
+// and `app_config()` points the app's upstream configuration at it.
+#![allow(dead_code)]
+
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App, HttpRequest, HttpResponse, HttpServer};
+use gtfs_realtime::FeedMessage;
+use prost::Message;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use trmnl_plugin_server::config::{AppConfig, BartConfig, MbtaConfig};
+use trmnl_plugin_server::handlers;
+use trmnl_plugin_server::utils::bart_etd::EtdConfig;
+
+pub const BART_TRIP_UPDATES_PATH: &str = "/bart/gtfsrt/tripupdate.aspx";
+pub const BART_ETD_PATH: &str = "/bart/api/etd.aspx";
+pub const BART_ALERTS_PATH: &str = "/bart/gtfsrt/alerts.aspx";
+pub const BART_SCHEDULE_PATH: &str = "/bart/dev/schedules/google_transit.zip";
+pub const MBTA_PATH: &str = "/mbta";
+pub const MBTA_TRIP_UPDATES_PATH: &str = "/mbta/realtime/TripUpdates.pb";
+pub const MBTA_VEHICLE_POSITIONS_PATH: &str = "/mbta/realtime/VehiclePositions.pb";
+pub const AGENCY_TRIP_UPDATES_PATH: &str = "/agency/gtfs-rt/trip-updates";
+pub const AGENCY_ALERTS_PATH: &str = "/agency/gtfs-rt/alerts";
+
+// What the mock server answers for one path
+#[derive(Clone)]
+pub enum Fixture {
+    Body {
+        content_type: &'static str,
+        body: Vec<u8>,
+    },
+    Status(u16),
+    // Wait before answering, for exercising upstream timeouts
+    Delayed(Duration, Box<Fixture>),
+    // One fixture per request in order, the last one repeating, e.g. a 429 then a good answer
+    Sequence(Vec<Fixture>),
+}
+
+impl Fixture {
+    pub fn protobuf(body: Vec<u8>) -> Self {
+        Fixture::Body {
+            content_type: "application/x-protobuf",
+            body,
+        }
+    }
+
+    pub fn json(value: Value) -> Self {
+        Fixture::Body {
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    pub fn text(body: &str) -> Self {
+        Fixture::Body {
+            content_type: "text/plain",
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn delayed(self, delay: Duration) -> Self {
+        Fixture::Delayed(delay, Box::new(self))
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    fixtures: Mutex<HashMap<String, Fixture>>,
+    hits: Mutex<HashMap<String, usize>>,
+    // Headers of the latest request to each path
+    headers: Mutex<HashMap<String, actix_web::http::header::HeaderMap>>,
+}
+
+pub struct MockUpstream {
+    pub base_url: String,
+    state: Arc<MockState>,
+}
+
+async fn serve_fixture(req: HttpRequest, state: web::Data<Arc<MockState>>) -> HttpResponse {
+    let path = req.path().to_string();
+    let hit = {
+        let mut hits = state.hits.lock().unwrap();
+        let hits = hits.entry(path.clone()).or_insert(0);
+        *hits += 1;
+        *hits
+    };
+    state.headers.lock().unwrap().insert(path.clone(), req.headers().clone());
+
+    let mut fixture = state.fixtures.lock().unwrap().get(&path).cloned();
+    loop {
+        match fixture {
+            Some(Fixture::Sequence(fixtures)) => {
+                fixture = fixtures.get(hit - 1).or(fixtures.last()).cloned();
+            }
+            Some(Fixture::Delayed(delay, inner)) => {
+                actix_web::rt::time::sleep(delay).await;
+                fixture = Some(*inner);
+            }
+            Some(Fixture::Body { content_type, body }) => {
+                return HttpResponse::Ok().content_type(content_type).body(body);
+            }
+            Some(Fixture::Status(status)) => {
+                return HttpResponse::build(StatusCode::from_u16(status).unwrap()).body("mock upstream error");
+            }
+            None => return HttpResponse::NotFound().body(format!("no fixture for {}", path)),
+        }
+    }
+}
+
+impl MockUpstream {
+    pub async fn start() -> Self {
+        let state = Arc::new(MockState::default());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind mock upstream");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(server_state.clone()))
+                .default_service(web::to(serve_fixture))
+        })
+        .workers(1)
+        .listen(listener)
+        .expect("listen on mock upstream")
+        .run();
+        actix_web::rt::spawn(server);
+
+        MockUpstream { base_url, state }
+    }
+
+    // Register (or replace) the fixture served at `path`
+    pub fn serve(&self, path: &str, fixture: Fixture) -> &Self {
+        self.state.fixtures.lock().unwrap().insert(path.to_string(), fixture);
+        self
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    // How many requests reached `path`
+    pub fn hits(&self, path: &str) -> usize {
+        self.state.hits.lock().unwrap().get(path).copied().unwrap_or(0)
+    }
+
+    // A header of the latest request to `path`
+    pub fn header(&self, path: &str, name: &str) -> Option<String> {
+        self.state
+            .headers
+            .lock()
+            .unwrap()
+            .get(path)?
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    }
+
+    // App configuration with every upstream pointed at this server
+    pub fn app_config(&self) -> AppConfig {
+        AppConfig {
+            upstream_timeout_ms: 500,
+            bart: BartConfig {
+                trip_update_url: self.url(BART_TRIP_UPDATES_PATH),
+                alerts_url: self.url(BART_ALERTS_PATH),
+                schedule: Some(self.url(BART_SCHEDULE_PATH)),
+                etd: EtdConfig {
+                    url: self.url(BART_ETD_PATH),
+                    api_key: Some("test-key".to_string()),
+                    ..EtdConfig::default()
+                },
+                ..BartConfig::default()
+            },
+            mbta: MbtaConfig {
+                base_url: self.url(MBTA_PATH),
+                trip_updates_url: self.url(MBTA_TRIP_UPDATES_PATH),
+                vehicle_positions_url: self.url(MBTA_VEHICLE_POSITIONS_PATH),
+                ..MbtaConfig::default()
+            },
+            agencies: HashMap::new(),
+            ..AppConfig::default()
+        }
+    }
+}
+
+// A recorded BART snapshot from tests/fixtures with every time moved so that the
+// recording's `offset` seconds after its header timestamp lines up with now
+pub fn bart_feed_fixture(file: &str, offset: i64) -> Vec<u8> {
+    let bytes = std::fs::read(format!("tests/fixtures/bart_feed/{}", file)).expect("read BART fixture");
+    let mut feed = FeedMessage::decode(bytes.as_slice()).expect("decode BART fixture");
+
+    let recorded_at = feed.header.timestamp.unwrap_or_default() as i64;
+    let shift = chrono::Utc::now().timestamp() - recorded_at - offset;
+
+    feed.header.timestamp = Some((recorded_at + shift) as u64);
+    for entity in feed.entity.iter_mut() {
+        if let Some(trip_update) = entity.trip_update.as_mut() {
+            for stop_time_update in trip_update.stop_time_update.iter_mut() {
+                for event in [stop_time_update.arrival.as_mut(), stop_time_update.departure.as_mut()]
+                    .into_iter()
+                    .flatten()
+                {
+                    event.time = event.time.map(|time| time + shift);
+                }
+            }
+        }
+    }
+
+    feed.encode_to_vec()
+}
+
+// tests/fixtures/bart_schedule zipped up like BART's published GTFS
+pub fn bart_schedule_zip() -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for entry in std::fs::read_dir("tests/fixtures/bart_schedule").expect("read BART schedule fixture") {
+        let path = entry.expect("read BART schedule fixture").path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        zip.start_file(name, zip::write::FileOptions::default()).unwrap();
+        zip.write_all(&std::fs::read(&path).unwrap()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+// Run one request through the full app (same route table as the server binary)
+pub async fn call(config: AppConfig, req: test::TestRequest) -> (StatusCode, Vec<u8>) {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(config))
+            .configure(handlers::configure),
+    )
+    .await;
+
+    let resp = test::call_service(&app, req.to_request()).await;
+    let status = resp.status();
+    let body = test::read_body(resp).await;
+    (status, body.to_vec())
+}
+
+// POST a JSON body and parse the JSON response (Value::Null when the body isn't JSON)
+pub async fn post_json(config: AppConfig, uri: &str, body: Value) -> (StatusCode, Value) {
+    let (status, body) = call(config, test::TestRequest::post().uri(uri).set_json(&body)).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+// GET a URI and parse the JSON response (Value::Null when the body isn't JSON)
+pub async fn get_json(config: AppConfig, uri: &str) -> (StatusCode, Value) {
+    let (status, body) = call(config, test::TestRequest::get().uri(uri)).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
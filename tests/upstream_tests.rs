@@ -0,0 +1,1482 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use actix_web::{web, App};
+use common::{
+    bart_feed_fixture, bart_schedule_zip, call, get_json, post_json, Fixture, MockUpstream, AGENCY_TRIP_UPDATES_PATH, BART_ALERTS_PATH, BART_ETD_PATH,
+    BART_SCHEDULE_PATH, BART_TRIP_UPDATES_PATH, MBTA_PATH, MBTA_TRIP_UPDATES_PATH, MBTA_VEHICLE_POSITIONS_PATH,
+};
+use gtfs_realtime::trip_update::{StopTimeEvent, StopTimeUpdate};
+use gtfs_realtime::trip_descriptor::ScheduleRelationship;
+use gtfs_realtime::vehicle_position::VehicleStopStatus;
+use gtfs_realtime::alert::{Effect, SeverityLevel};
+use gtfs_realtime::translated_string::Translation;
+use gtfs_realtime::{
+    Alert, EntitySelector, FeedEntity, FeedMessage, TranslatedString, TripDescriptor, TripUpdate, VehiclePosition,
+};
+use prost::Message;
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+use trmnl_plugin_server::config::{AgencyConfig, AppConfig, MbtaSource};
+use trmnl_plugin_server::handlers;
+use trmnl_plugin_server::tasks::bart_schedule;
+use trmnl_plugin_server::utils::feed_source::FeedMode;
+use trmnl_plugin_server::utils::gtfs_helper;
+use trmnl_plugin_server::utils::mbta_api::MbtaClient;
+
+fn walnut_creek_southbound() -> serde_json::Value {
+    serde_json::json!({
+        "station": "Walnut Creek",
+        "line": "Yellow",
+        "direction": 1,
+        "show_time_not_minutes": false
+    })
+}
+
+fn etd_estimate(minutes: &str, direction: &str, color: &str, length: &str) -> serde_json::Value {
+    serde_json::json!({
+        "minutes": minutes,
+        "platform": if direction == "South" { "1" } else { "2" },
+        "direction": direction,
+        "length": length,
+        "color": color,
+        "hexcolor": "#ffff33",
+        "bikeflag": "1",
+        "delay": "0",
+        "cancelflag": "0",
+        "dynamicflag": "0"
+    })
+}
+
+// ETD API answer for Walnut Creek: two southbound Yellow trains and a northbound one
+fn walnut_creek_etd() -> serde_json::Value {
+    serde_json::json!({
+        "root": {
+            "station": [{
+                "name": "Walnut Creek",
+                "abbr": "WCRK",
+                "etd": [
+                    {
+                        "destination": "SF Airport",
+                        "abbreviation": "SFIA",
+                        "limited": "0",
+                        "estimate": [
+                            etd_estimate("2", "South", "YELLOW", "10"),
+                            etd_estimate("12", "South", "YELLOW", "8")
+                        ]
+                    },
+                    {
+                        "destination": "Antioch",
+                        "abbreviation": "ANTC",
+                        "limited": "0",
+                        "estimate": [etd_estimate("5", "North", "YELLOW", "6")]
+                    }
+                ]
+            }],
+            "message": ""
+        }
+    })
+}
+
+// V3 API stops listing with two parent stations
+fn mbta_stations() -> serde_json::Value {
+    serde_json::json!({
+        "data": [
+            {"type": "stop", "id": "place-sstat", "attributes": {"name": "South Station", "location_type": 1}},
+            {"type": "stop", "id": "place-north", "attributes": {"name": "North Station", "location_type": 1}}
+        ]
+    })
+}
+
+// `offset` seconds from now, as the V3 API writes times
+fn mbta_time(offset: i64) -> String {
+    chrono::DateTime::from_timestamp(chrono::Utc::now().timestamp() + offset, 0)
+        .unwrap()
+        .with_timezone(&chrono_tz::America::New_York)
+        .to_rfc3339()
+}
+
+// A prediction or schedule `offset` seconds from now, with its route and trip as relationships
+fn mbta_stop_time(kind: &str, route: &str, trip: &str, offset: Option<i64>, status: Option<&str>) -> serde_json::Value {
+    let departure = offset.map(mbta_time);
+    serde_json::json!({
+        "type": kind,
+        "id": format!("{}-{}", kind, trip),
+        "attributes": {"departure_time": departure, "arrival_time": departure, "direction_id": 0, "status": status},
+        "relationships": {
+            "route": {"data": {"type": "route", "id": route}},
+            "trip": {"data": {"type": "trip", "id": trip}},
+            "stop": {"data": {"type": "stop", "id": "70079"}}
+        }
+    })
+}
+
+fn mbta_included() -> serde_json::Value {
+    serde_json::json!([
+        {"type": "route", "id": "Red", "attributes": {"short_name": "", "long_name": "Red Line", "type": 1}},
+        {"type": "route", "id": "CR-Providence", "attributes": {"short_name": "", "long_name": "Providence/Stoughton Line", "type": 2}},
+        {"type": "trip", "id": "red-1", "attributes": {"headsign": "Alewife"}},
+        {"type": "trip", "id": "red-2", "attributes": {"headsign": "Alewife"}},
+        {"type": "trip", "id": "red-0", "attributes": {"headsign": "Ashmont"}},
+        {"type": "trip", "id": "red-end", "attributes": {"headsign": "South Station"}},
+        {"type": "trip", "id": "cr-801", "attributes": {"headsign": "Providence"}}
+    ])
+}
+
+// South Station: three departures still to come, one that just left, and one terminating train
+fn serve_south_station(upstream: &MockUpstream) {
+    upstream.serve(&format!("{}/stops", MBTA_PATH), Fixture::json(mbta_stations()));
+    upstream.serve(
+        &format!("{}/predictions", MBTA_PATH),
+        Fixture::json(serde_json::json!({
+            "data": [
+                mbta_stop_time("prediction", "Red", "red-0", Some(-60), None),
+                mbta_stop_time("prediction", "Red", "red-end", None, None),
+                mbta_stop_time("prediction", "Red", "red-1", Some(150), None),
+                mbta_stop_time("prediction", "CR-Providence", "cr-801", Some(400), Some("On time")),
+                mbta_stop_time("prediction", "Red", "red-2", Some(630), None)
+            ],
+            "included": mbta_included()
+        })),
+    );
+    upstream.serve(
+        &format!("{}/schedules", MBTA_PATH),
+        Fixture::json(serde_json::json!({
+            "data": [mbta_stop_time("schedule", "Red", "red-0", Some(-190), None)],
+            "included": mbta_included()
+        })),
+    );
+}
+
+// An MBTA GTFS-RT trip update: (stop_id, departure offset from now) pairs, None for a
+// train terminating there (arriving in 90s)
+fn mbta_trip_update(trip_id: &str, route_id: &str, stops: &[(&str, Option<i64>)]) -> FeedEntity {
+    let now = chrono::Utc::now().timestamp();
+    FeedEntity {
+        id: trip_id.to_string(),
+        trip_update: Some(TripUpdate {
+            trip: TripDescriptor {
+                trip_id: Some(trip_id.to_string()),
+                route_id: Some(route_id.to_string()),
+                direction_id: Some(0),
+                ..Default::default()
+            },
+            stop_time_update: stops
+                .iter()
+                .map(|(stop_id, offset)| StopTimeUpdate {
+                    stop_id: Some(stop_id.to_string()),
+                    arrival: Some(StopTimeEvent {
+                        time: Some(now + offset.unwrap_or(90)),
+                        ..Default::default()
+                    }),
+                    departure: offset.map(|offset| StopTimeEvent {
+                        time: Some(now + offset),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[actix_web::test]
+async fn test_bart_live_feed() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)),
+    );
+
+    let (status, json) = post_json(upstream.app_config(), "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(upstream.hits(BART_TRIP_UPDATES_PATH), 1);
+    assert_eq!(
+        json["outbound_train"],
+        "Yellow to San Francisco International Airport - 2 min (On time)"
+    );
+    assert_eq!(json["trains"].as_array().unwrap().len(), 3);
+    assert_eq!(json["inbound_train_0"], "Yellow to San Francisco International Airport - 12 min (3 min late)");
+}
+
+#[actix_web::test]
+async fn test_bart_unknown_station() {
+    let upstream = MockUpstream::start().await;
+
+    let mut body = walnut_creek_southbound();
+    body["station"] = "Atlantis".into();
+    let (status, json) = post_json(upstream.app_config(), "/BART", body).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["error"], "station_not_found");
+    assert_eq!(upstream.hits(BART_TRIP_UPDATES_PATH), 0, "Bad requests shouldn't reach the upstream");
+}
+
+// A station name buried in a longer query isn't matched, like MBTA station names
+#[actix_web::test]
+async fn test_bart_station_inside_query_not_matched() {
+    let upstream = MockUpstream::start().await;
+
+    let mut body = walnut_creek_southbound();
+    body["station"] = "Walnut Creek parking garage".into();
+    let (status, json) = post_json(upstream.app_config(), "/BART", body).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["error"], "station_not_found");
+}
+
+#[actix_web::test]
+async fn test_bart_upstream_server_error() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(BART_TRIP_UPDATES_PATH, Fixture::Status(503));
+
+    let (status, json) = post_json(upstream.app_config(), "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(json["error"], "upstream_unavailable");
+}
+
+#[actix_web::test]
+async fn test_bart_bad_protobuf() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(BART_TRIP_UPDATES_PATH, Fixture::protobuf(vec![0xff, 0xff, 0xff, 0xff]));
+
+    let (status, json) = post_json(upstream.app_config(), "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(json["error"], "decode_failed");
+}
+
+#[actix_web::test]
+async fn test_bart_upstream_timeout() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)).delayed(Duration::from_secs(2)),
+    );
+
+    let started = std::time::Instant::now();
+    let (status, json) = post_json(upstream.app_config(), "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(json["error"], "upstream_unavailable");
+    assert!(started.elapsed() < Duration::from_secs(2), "The upstream timeout should cut the request short");
+}
+
+// ETD estimates line up with the GTFS-RT predictions and add the train length
+#[actix_web::test]
+async fn test_bart_etd_adds_car_counts() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)),
+    );
+    upstream.serve(BART_ETD_PATH, Fixture::json(walnut_creek_etd()));
+    let mut config = upstream.app_config();
+    config.bart.etd.enabled = true;
+
+    let (status, json) = post_json(config, "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(upstream.hits(BART_ETD_PATH), 1);
+    let trains = json["trains"].as_array().unwrap();
+    assert_eq!(trains.len(), 3, "Matched estimates shouldn't add trains");
+    assert_eq!(trains[0]["cars"], 10);
+    assert_eq!(trains[0]["source"], "gtfs_rt");
+    assert_eq!(trains[1]["cars"], 8);
+    assert!(trains[2]["cars"].is_null());
+}
+
+// With the trip-update feed down the board is built from ETD alone
+#[actix_web::test]
+async fn test_bart_etd_fills_missing_feed() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(BART_TRIP_UPDATES_PATH, Fixture::Status(503));
+    upstream.serve(BART_ETD_PATH, Fixture::json(walnut_creek_etd()));
+    let mut config = upstream.app_config();
+    config.bart.etd.enabled = true;
+
+    let mut body = walnut_creek_southbound();
+    body["direction"] = (-1).into();
+    let (status, json) = post_json(config, "/BART", body).await;
+
+    assert_eq!(status, StatusCode::OK);
+    let trains = json["trains"].as_array().unwrap();
+    assert_eq!(trains.len(), 3);
+    assert!(trains.iter().all(|train| train["source"] == "etd"));
+    assert_eq!(trains[0]["destination"], "SF Airport");
+    assert_eq!(trains[0]["stop_id"], "C40-1");
+    assert_eq!(trains[0]["route_id"], "1");
+    assert_eq!(trains[1]["destination"], "Antioch");
+    assert_eq!(trains[1]["cars"], 6);
+}
+
+// A GTFS-RT alert informing `entity`, in effect for the next hour
+fn gtfs_rt_alert(id: &str, effect: Effect, severity: SeverityLevel, header: &str, entity: EntitySelector) -> FeedEntity {
+    let now = chrono::Utc::now().timestamp() as u64;
+    FeedEntity {
+        id: id.to_string(),
+        alert: Some(Alert {
+            active_period: vec![gtfs_realtime::TimeRange {
+                start: Some(now - 600),
+                end: Some(now + 3600),
+            }],
+            informed_entity: vec![entity],
+            effect: Some(effect as i32),
+            severity_level: Some(severity as i32),
+            header_text: Some(TranslatedString {
+                translation: vec![Translation {
+                    text: header.to_string(),
+                    language: Some("en".to_string()),
+                }],
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+// BART alerts go through the same handler as MBTA's: agency-wide alerts show everywhere,
+// stop alerts only at their station, and accessibility issues are facility alerts
+#[actix_web::test]
+async fn test_bart_alerts() {
+    let upstream = MockUpstream::start().await;
+    let at_stop = |stop_id: &str| EntitySelector {
+        stop_id: Some(stop_id.to_string()),
+        ..Default::default()
+    };
+    let feed = FeedMessage {
+        entity: vec![
+            gtfs_rt_alert(
+                "bsa",
+                Effect::SignificantDelays,
+                SeverityLevel::Warning,
+                "10 minute delay systemwide",
+                EntitySelector {
+                    agency_id: Some("BART".to_string()),
+                    ..Default::default()
+                },
+            ),
+            gtfs_rt_alert("wcrk", Effect::StopMoved, SeverityLevel::Info, "Platform 1 closed at Walnut Creek", at_stop("C40-1")),
+            gtfs_rt_alert("embr", Effect::NoService, SeverityLevel::Severe, "No service at Embarcadero", at_stop("EMBR")),
+            gtfs_rt_alert("elevator", Effect::AccessibilityIssue, SeverityLevel::Info, "Walnut Creek elevator out", at_stop("WCRK")),
+        ],
+        ..Default::default()
+    };
+    upstream.serve(BART_ALERTS_PATH, Fixture::protobuf(feed.encode_to_vec()));
+
+    let (status, json) = get_json(upstream.app_config(), "/BART/alerts?station=Walnut%20Creek").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["station"], "Walnut Creek");
+    assert_eq!(
+        json["service_alerts"],
+        serde_json::json!(["10 minute delay systemwide", "Platform 1 closed at Walnut Creek"])
+    );
+    assert_eq!(json["facility_alerts"], serde_json::json!(["Walnut Creek elevator out"]));
+    assert_eq!(json["alerts"][0]["effect"], "SIGNIFICANT_DELAYS");
+
+    let (_, json) = get_json(upstream.app_config(), "/BART/alerts?route=Yellow").await;
+    assert_eq!(json["service_alerts"], serde_json::json!(["10 minute delay systemwide"]));
+}
+
+// Alerts with no active period are in effect until taken down, and rank after ones that
+// started recently
+#[actix_web::test]
+async fn test_bart_alerts_without_period() {
+    let upstream = MockUpstream::start().await;
+    let everywhere = || EntitySelector {
+        agency_id: Some("BART".to_string()),
+        ..Default::default()
+    };
+    let mut standing = gtfs_rt_alert("standing", Effect::OtherEffect, SeverityLevel::Warning, "Masks recommended", everywhere());
+    standing.alert.as_mut().unwrap().active_period.clear();
+    let feed = FeedMessage {
+        entity: vec![
+            standing,
+            gtfs_rt_alert("bsa", Effect::SignificantDelays, SeverityLevel::Warning, "10 minute delay systemwide", everywhere()),
+        ],
+        ..Default::default()
+    };
+    upstream.serve(BART_ALERTS_PATH, Fixture::protobuf(feed.encode_to_vec()));
+
+    let (status, json) = get_json(upstream.app_config(), "/BART/alerts?station=Walnut%20Creek").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        json["service_alerts"],
+        serde_json::json!(["10 minute delay systemwide", "Masks recommended"])
+    );
+    assert_eq!(json["alerts"][1]["active"], true);
+    assert!(json["alerts"][1]["starts_at"].is_null());
+}
+
+// Home and work boards in one request, built from a single fetch of the feed
+#[actix_web::test]
+async fn test_bart_multi_station() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)),
+    );
+
+    let body = serde_json::json!({
+        "queries": [
+            walnut_creek_southbound(),
+            { "station": "Embarcadero", "line": "Red", "direction": -1, "show_time_not_minutes": false }
+        ]
+    });
+    let (status, json) = post_json(upstream.app_config(), "/BART", body).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(upstream.hits(BART_TRIP_UPDATES_PATH), 1);
+    let sections = json["sections"].as_array().unwrap();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0]["station"], "Walnut Creek");
+    assert_eq!(sections[0]["query"]["line"], "Yellow");
+    assert_eq!(sections[0]["trains"].as_array().unwrap().len(), 3);
+    assert_eq!(sections[1]["station"], "Embarcadero");
+    assert_eq!(sections[1]["trains"].as_array().unwrap().len(), 2);
+    assert_eq!(sections[1]["trains"][0]["line"], "Red");
+}
+
+#[actix_web::test]
+async fn test_bart_multi_station_unknown_station() {
+    let upstream = MockUpstream::start().await;
+
+    let body = serde_json::json!({
+        "queries": [
+            walnut_creek_southbound(),
+            { "station": "Atlantis", "line": "", "direction": -1, "show_time_not_minutes": false }
+        ]
+    });
+    let (status, json) = post_json(upstream.app_config(), "/BART", body).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["error"], "station_not_found");
+    assert_eq!(upstream.hits(BART_TRIP_UPDATES_PATH), 0);
+}
+
+// Devices are told to come back when the board next changes, in the body and the caching headers
+#[actix_web::test]
+async fn test_bart_refresh_hint() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)),
+    );
+
+    // A minutes countdown needs a new render every minute
+    let (status, json) = post_json(upstream.app_config(), "/BART", walnut_creek_southbound()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["refresh"]["refresh_in_seconds"], 60);
+
+    // Clock times hold until just after the next train (150s away) leaves
+    let mut body = walnut_creek_southbound();
+    body["show_time_not_minutes"] = true.into();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(upstream.app_config()))
+            .configure(handlers::configure),
+    )
+    .await;
+    let resp = test::call_service(&app, TestRequest::post().uri("/BART").set_json(&body).to_request()).await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    let cache_control = resp.headers().get("cache-control").unwrap().to_str().unwrap().to_string();
+    assert!(resp.headers().contains_key("expires"));
+    let json: serde_json::Value = test::read_body_json(resp).await;
+    let refresh_in = json["refresh"]["refresh_in_seconds"].as_i64().unwrap();
+    assert!((178..=180).contains(&refresh_in), "refresh_in_seconds was {}", refresh_in);
+    assert_eq!(cache_control, format!("public, max-age={}", refresh_in));
+}
+
+// A feed that stopped updating ten minutes ago isn't passed off as live
+#[actix_web::test]
+async fn test_bart_stale_feed() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 600)),
+    );
+
+    let (status, json) = post_json(upstream.app_config(), "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(json["error"], "data_stale");
+    assert_eq!(json["message"], "Live data is out of date");
+}
+
+// Bodies that aren't JSON at all get the same error shape as the handlers' own errors
+#[actix_web::test]
+async fn test_malformed_json_body() {
+    let upstream = MockUpstream::start().await;
+
+    let (status, body) = call(
+        upstream.app_config(),
+        TestRequest::post()
+            .uri("/BART")
+            .insert_header(("content-type", "application/json"))
+            .set_payload("{\"station\": "),
+    )
+    .await;
+    let json: serde_json::Value = serde_json::from_slice(&body).expect("error body should be JSON");
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["error"], "bad_request");
+    assert_eq!(json["message"], "Invalid request");
+}
+
+// A train that drops out of the feed after leaving is still reported as the passed train
+#[actix_web::test]
+async fn test_bart_passed_train() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)),
+    );
+    let mut config = upstream.app_config();
+    config.bart.cache_seconds = 0;
+
+    let (status, json) = post_json(config.clone(), "/BART", walnut_creek_southbound()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["passed_train"], "No recent departures");
+    assert!(json["last_departed"].is_null());
+
+    // Five minutes into the recording the first southbound train left two minutes ago
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 300)),
+    );
+    let (status, json) = post_json(config, "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["last_departed"]["trip_id"], "1682335");
+    assert_eq!(json["last_departed"]["minutes_ago"], 2);
+    assert_eq!(
+        json["passed_train"],
+        "Yellow to San Francisco International Airport left 2 min ago"
+    );
+    assert_eq!(json["next_is_last"], false);
+}
+
+// Southbound Yellow trips at Walnut Creek predicted `minutes` from now
+fn walnut_creek_trips(trips: &[(&str, i64)]) -> FeedMessage {
+    let now = chrono::Utc::now().timestamp();
+    FeedMessage {
+        entity: trips
+            .iter()
+            .map(|(trip_id, minutes)| FeedEntity {
+                id: trip_id.to_string(),
+                trip_update: Some(TripUpdate {
+                    trip: TripDescriptor {
+                        trip_id: Some(trip_id.to_string()),
+                        ..Default::default()
+                    },
+                    stop_time_update: vec![StopTimeUpdate {
+                        stop_id: Some("C40-1".to_string()),
+                        departure: Some(StopTimeEvent {
+                            time: Some(now + minutes * 60),
+                            delay: Some(0),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+// The night's final southbound Yellow trip is the latest departure from the station in
+// stop_times.txt, not the last row of trips.txt (1682338) or the highest trip id (1682362)
+#[actix_web::test]
+async fn test_bart_next_is_last_train() {
+    let upstream = MockUpstream::start().await;
+    let mut config = upstream.app_config();
+    config.bart.schedule = Some("tests/fixtures/bart_schedule".to_string());
+    bart_schedule::refresh_bart_schedule(&config).await.unwrap();
+
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(walnut_creek_trips(&[("1682338", 2), ("1682337", 5)]).encode_to_vec()),
+    );
+    let (status, json) = post_json(config, "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["next_is_last"], false);
+    assert_eq!(json["headway"]["last_train_known"], true);
+    assert_eq!(json["headway"]["last_train"]["trip_id"], "1682337");
+
+    // Once it's the next train (a fresh upstream, the feed cache is per URL)
+    let upstream = MockUpstream::start().await;
+    let mut config = upstream.app_config();
+    config.bart.schedule = Some("tests/fixtures/bart_schedule".to_string());
+    bart_schedule::refresh_bart_schedule(&config).await.unwrap();
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(walnut_creek_trips(&[("1682337", 5)]).encode_to_vec()),
+    );
+    let (status, json) = post_json(config, "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["next_is_last"], true);
+    assert!(json["headway"]["frequency_text"].as_str().unwrap().starts_with("Last train at"));
+}
+
+// Without stop times for the station there's no last-train info, rather than a guess
+#[actix_web::test]
+async fn test_bart_last_train_unknown_without_stop_times() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(walnut_creek_trips(&[("1682337", 5)]).encode_to_vec()),
+    );
+
+    let (status, json) = post_json(upstream.app_config(), "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["headway"]["last_train_known"], false);
+    assert_eq!(json["headway"]["last_train"], serde_json::Value::Null);
+    assert_eq!(json["next_is_last"], false);
+    assert_eq!(json["headway"]["frequency_text"], "One upcoming train");
+}
+
+// Out of the box the schedule comes from BART's published GTFS zip, loaded in the
+// background rather than by the board, so the OAK connector shows without any schedule
+// configuration
+#[actix_web::test]
+async fn test_bart_oak_connector_default_schedule() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_SCHEDULE_PATH,
+        Fixture::Body {
+            content_type: "application/zip",
+            body: bart_schedule_zip(),
+        },
+    );
+    let mut config = upstream.app_config();
+    config.bart.feed = FeedMode::Replay {
+        dir: "tests/fixtures/bart_feed".to_string(),
+        start_time: Some(1741104030),
+        speed: 0.0,
+    };
+    let request = serde_json::json!({"station": "Coliseum", "line": "Grey", "direction": 1, "show_time_not_minutes": false});
+
+    // Boards don't download the schedule themselves
+    let (status, json) = post_json(config.clone(), "/BART", request.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["outbound_train"], "No data available");
+    assert_eq!(upstream.hits(BART_SCHEDULE_PATH), 0);
+
+    bart_schedule::refresh_bart_schedule(&config).await.unwrap();
+    let (status, json) = post_json(config.clone(), "/BART", request.clone()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["outbound_train"], "Grey to Oakland Airport - 3 min (No live data)");
+    assert_eq!(json["trains"][0]["live"], false);
+
+    post_json(config, "/BART", request).await;
+    assert_eq!(upstream.hits(BART_SCHEDULE_PATH), 1);
+}
+
+// A schedule that can't be downloaded leaves the realtime board as it is, and the last
+// copy that loaded stays in use
+#[actix_web::test]
+async fn test_bart_schedule_unavailable() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(BART_SCHEDULE_PATH, Fixture::Status(503));
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)),
+    );
+
+    assert!(bart_schedule::refresh_bart_schedule(&upstream.app_config()).await.is_err());
+    let (status, json) = post_json(upstream.app_config(), "/BART", walnut_creek_southbound()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["trains"].as_array().unwrap().len(), 3);
+    assert_eq!(json["headway"]["last_train_known"], false);
+
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_SCHEDULE_PATH,
+        Fixture::Sequence(vec![
+            Fixture::Body {
+                content_type: "application/zip",
+                body: bart_schedule_zip(),
+            },
+            Fixture::Status(503),
+        ]),
+    );
+    let config = upstream.app_config();
+    bart_schedule::refresh_bart_schedule(&config).await.unwrap();
+    assert!(bart_schedule::refresh_bart_schedule(&config).await.is_err());
+    assert!(bart_schedule::bart_schedule(&config).is_some());
+    assert_eq!(upstream.hits(BART_SCHEDULE_PATH), 2);
+}
+
+// Requests asking for the same static GTFS while it's still loading share one download
+#[actix_web::test]
+async fn test_gtfs_source_single_download() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_SCHEDULE_PATH,
+        Fixture::Delayed(
+            Duration::from_millis(200),
+            Box::new(Fixture::Body {
+                content_type: "application/zip",
+                body: bart_schedule_zip(),
+            }),
+        ),
+    );
+    let source = upstream.url(BART_SCHEDULE_PATH);
+    let client = reqwest::Client::new();
+    let headers = HashMap::new();
+
+    let (first, second) = tokio::join!(
+        gtfs_helper::load_source_cached(&source, &client, &headers),
+        gtfs_helper::load_source_cached(&source, &client, &headers)
+    );
+
+    assert!(std::sync::Arc::ptr_eq(&first.unwrap(), &second.unwrap()));
+    assert_eq!(upstream.hits(BART_SCHEDULE_PATH), 1);
+}
+
+// ETD is optional, by default only the GTFS-RT feed is queried
+#[actix_web::test]
+async fn test_bart_etd_disabled_by_default() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(
+        BART_TRIP_UPDATES_PATH,
+        Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)),
+    );
+
+    let (status, json) = post_json(upstream.app_config(), "/BART", walnut_creek_southbound()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(upstream.hits(BART_ETD_PATH), 0);
+    assert!(json["trains"][0]["cars"].is_null());
+}
+
+// There's no built-in ETD key, so turning ETD on without one is a config error
+#[actix_web::test]
+async fn test_bart_etd_needs_api_key() {
+    let mut config = AppConfig::default();
+    config.bart.etd.enabled = true;
+    assert!(config.validate().is_err());
+
+    config.bart.etd.api_key = Some("test-key".to_string());
+    assert!(config.validate().is_ok());
+}
+
+// A mistyped timezone fails at startup rather than quietly showing UTC
+#[actix_web::test]
+async fn test_config_rejects_unknown_timezone() {
+    let config = AppConfig {
+        timezone: "America/Los_Angles".to_string(),
+        ..AppConfig::default()
+    };
+    assert!(config.validate().unwrap_err().contains("America/Los_Angles"));
+}
+
+// Four board lines for the teammates' plugin, padded when fewer trains are coming
+#[actix_web::test]
+async fn test_mbta_board() {
+    let upstream = MockUpstream::start().await;
+    serve_south_station(&upstream);
+
+    let (status, json) = post_json(upstream.app_config(), "/MBTA", serde_json::json!({"station_name": "south station"})).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["station"], "South Station");
+    let next_trains: Vec<&str> = json["next_trains"].as_array().unwrap().iter().map(|train| train.as_str().unwrap()).collect();
+    assert_eq!(
+        next_trains,
+        [
+            "Red Line to Alewife - 2 min",
+            "Providence/Stoughton Line to Providence - 6 min (On time)",
+            "Red Line to Alewife - 10 min",
+            "No data available"
+        ]
+    );
+    assert_eq!(json["passed_train"], "Red Line to Ashmont left 3 min ago");
+    assert_eq!(json["trains"].as_array().unwrap().len(), 3);
+}
+
+// A late train's scheduled time passes while it's still predicted; it isn't the passed train
+#[actix_web::test]
+async fn test_mbta_passed_train_skips_late_trains() {
+    let upstream = MockUpstream::start().await;
+    serve_south_station(&upstream);
+    upstream.serve(
+        &format!("{}/schedules", MBTA_PATH),
+        Fixture::json(serde_json::json!({
+            "data": [
+                mbta_stop_time("schedule", "Red", "red-1", Some(-60), None),
+                mbta_stop_time("schedule", "Red", "red-0", Some(-190), None)
+            ],
+            "included": mbta_included()
+        })),
+    );
+
+    let (_, json) = post_json(upstream.app_config(), "/MBTA", serde_json::json!({"station_name": "South Station"})).await;
+
+    assert_eq!(json["next_trains"][0], "Red Line to Alewife - 2 min");
+    assert_eq!(json["passed_train"], "Red Line to Ashmont left 3 min ago");
+    assert_eq!(json["last_departed"]["trip_id"], "red-0");
+}
+
+#[actix_web::test]
+async fn test_mbta_unknown_station() {
+    let upstream = MockUpstream::start().await;
+    serve_south_station(&upstream);
+    // Names the local GTFS doesn't know are tried as stop ids in the V3 API
+    upstream.serve(&format!("{}/stops", MBTA_PATH), Fixture::json(serde_json::json!({"data": []})));
+
+    let (status, json) = post_json(upstream.app_config(), "/MBTA", serde_json::json!({"station_name": "Narnia"})).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["error"], "station_not_found");
+}
+
+// South Station serves several modes; `mode` and `route` narrow the board down, and commuter
+// rail departures carry their track
+#[actix_web::test]
+async fn test_mbta_mode_and_route_filters() {
+    let upstream = MockUpstream::start().await;
+    let mut commuter_rail = mbta_stop_time("prediction", "CR-Providence", "cr-801", Some(400), Some("On time"));
+    commuter_rail["relationships"]["stop"]["data"]["id"] = "NEC-2287-03".into();
+    let mut included = mbta_included();
+    included.as_array_mut().unwrap().extend([
+        serde_json::json!({"type": "stop", "id": "NEC-2287-03", "attributes": {"name": "South Station", "platform_code": "3"}}),
+        serde_json::json!({"type": "stop", "id": "70079", "attributes": {"name": "South Station", "platform_code": "1"}}),
+        serde_json::json!({"type": "route", "id": "Green-B", "attributes": {"short_name": "B", "long_name": "Green Line B", "type": 0}}),
+        serde_json::json!({"type": "route", "id": "Green-E", "attributes": {"short_name": "E", "long_name": "Green Line E", "type": 0}}),
+        serde_json::json!({"type": "route", "id": "1", "attributes": {"short_name": "1", "long_name": "Harvard Square - Nubian Station", "type": 3}}),
+        serde_json::json!({"type": "trip", "id": "green-b-0", "attributes": {"headsign": "Boston College"}}),
+        serde_json::json!({"type": "trip", "id": "green-b-1", "attributes": {"headsign": "Boston College"}}),
+        serde_json::json!({"type": "trip", "id": "green-e-1", "attributes": {"headsign": "Heath Street"}}),
+        serde_json::json!({"type": "trip", "id": "bus-1", "attributes": {"headsign": "Harvard"}}),
+    ]);
+    upstream.serve(
+        &format!("{}/predictions", MBTA_PATH),
+        Fixture::json(serde_json::json!({
+            "data": [
+                mbta_stop_time("prediction", "Red", "red-1", Some(150), None),
+                commuter_rail,
+                mbta_stop_time("prediction", "Green-B", "green-b-1", Some(200), None),
+                mbta_stop_time("prediction", "Green-E", "green-e-1", Some(500), None),
+                mbta_stop_time("prediction", "1", "bus-1", Some(320), None)
+            ],
+            "included": included
+        })),
+    );
+    upstream.serve(
+        &format!("{}/schedules", MBTA_PATH),
+        Fixture::json(serde_json::json!({
+            "data": [
+                mbta_stop_time("schedule", "Red", "red-0", Some(-60), None),
+                mbta_stop_time("schedule", "Green-B", "green-b-0", Some(-190), None)
+            ],
+            "included": included
+        })),
+    );
+    let board = |filter: serde_json::Value| {
+        let mut body = serde_json::json!({"station_name": "South Station"});
+        body.as_object_mut().unwrap().extend(filter.as_object().unwrap().clone());
+        post_json(upstream.app_config(), "/MBTA", body)
+    };
+
+    let (status, json) = board(serde_json::json!({"mode": "commuter_rail"})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["next_trains"][0], "Providence/Stoughton Line to Providence - 6 min, Track 3 (On time)");
+    assert_eq!(json["next_trains"][1], "No data available");
+    assert_eq!(json["trains"][0]["mode"], "commuter_rail");
+    assert_eq!(json["trains"][0]["track"], "3");
+    assert_eq!(json["passed_train"], "No recent departures");
+
+    let (_, json) = board(serde_json::json!({"route": "B"})).await;
+    let trains = json["trains"].as_array().unwrap();
+    assert_eq!(trains.len(), 1);
+    assert_eq!(trains[0]["branch"], "B");
+    assert_eq!(trains[0]["track"], serde_json::Value::Null);
+    assert_eq!(json["passed_train"], "Green Line B to Boston College left 3 min ago");
+
+    let (_, json) = board(serde_json::json!({"route": "green"})).await;
+    assert_eq!(json["trains"].as_array().unwrap().len(), 2);
+
+    let (_, json) = board(serde_json::json!({"mode": "bus"})).await;
+    assert_eq!(json["next_trains"][0], "[Bus] 1 to Harvard - 5 min");
+
+    let (status, _) = board(serde_json::json!({"mode": "rocket"})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// The next train's position comes along with its prediction; stops the embedded extract
+// doesn't know are named by the API
+#[actix_web::test]
+async fn test_mbta_next_train_location() {
+    let upstream = MockUpstream::start().await;
+    let mut red = mbta_stop_time("prediction", "Red", "red-1", Some(150), None);
+    red["relationships"]["vehicle"] = serde_json::json!({"data": {"type": "vehicle", "id": "R-5463"}});
+    let mut bus = mbta_stop_time("prediction", "1", "bus-1", Some(320), None);
+    bus["relationships"]["vehicle"] = serde_json::json!({"data": {"type": "vehicle", "id": "y1234"}});
+    let vehicle = |id: &str, trip: &str, stop: &str, status: &str| {
+        serde_json::json!({
+            "type": "vehicle",
+            "id": id,
+            "attributes": {"current_status": status, "label": id},
+            "relationships": {"trip": {"data": {"type": "trip", "id": trip}}, "stop": {"data": {"type": "stop", "id": stop}}}
+        })
+    };
+    let mut included = mbta_included();
+    included.as_array_mut().unwrap().extend([
+        vehicle("R-5463", "red-1", "70072", "STOPPED_AT"),
+        vehicle("y1234", "bus-1", "2168", "IN_TRANSIT_TO"),
+        serde_json::json!({"type": "route", "id": "1", "attributes": {"short_name": "1", "long_name": "Harvard Square - Nubian Station", "type": 3}}),
+    ]);
+    upstream.serve(
+        &format!("{}/predictions", MBTA_PATH),
+        Fixture::json(serde_json::json!({"data": [red, bus], "included": included})),
+    );
+    upstream.serve(&format!("{}/schedules", MBTA_PATH), Fixture::json(serde_json::json!({"data": []})));
+    upstream.serve(
+        &format!("{}/stops", MBTA_PATH),
+        Fixture::json(serde_json::json!({
+            "data": [{"type": "stop", "id": "2168", "attributes": {"name": "Massachusetts Ave @ Albany St"}}]
+        })),
+    );
+
+    let (status, json) = post_json(upstream.app_config(), "/MBTA", serde_json::json!({"station_name": "South Station"})).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["next_train_location"], "Train is at Kendall/MIT");
+    assert_eq!(json["trains"][0]["vehicle"]["stop_id"], "70072");
+    assert_eq!(json["trains"][1]["vehicle"]["text"], "Bus is on the way to Massachusetts Ave @ Albany St");
+    assert_eq!(upstream.hits(&format!("{}/stops", MBTA_PATH)), 1);
+}
+
+// Bus stops aren't in the embedded station extract, so they're looked up by id in the V3 API
+#[actix_web::test]
+async fn test_mbta_bus_stop() {
+    let upstream = MockUpstream::start().await;
+    serve_south_station(&upstream);
+    upstream.serve(
+        &format!("{}/stops", MBTA_PATH),
+        Fixture::json(serde_json::json!({
+            "data": [{"type": "stop", "id": "2168", "attributes": {"name": "Massachusetts Ave @ Albany St", "location_type": 0}}]
+        })),
+    );
+
+    let (status, json) = post_json(upstream.app_config(), "/MBTA", serde_json::json!({"station_name": "2168"})).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["station"], "Massachusetts Ave @ Albany St");
+    assert_eq!(upstream.hits(&format!("{}/stops", MBTA_PATH)), 1);
+}
+
+// An alert in effect from `start` to `end` seconds from now
+fn mbta_alert(id: &str, effect: &str, severity: u8, header: &str, start: i64, end: i64) -> serde_json::Value {
+    serde_json::json!({
+        "type": "alert",
+        "id": id,
+        "attributes": {
+            "header": header,
+            "short_header": "",
+            "effect": effect,
+            "severity": severity,
+            "active_period": [{"start": mbta_time(start), "end": mbta_time(end)}],
+            "informed_entity": [{"stop": "place-sstat", "activities": ["BOARD"]}]
+        }
+    })
+}
+
+// Service disruptions and elevator outages come back as separate quadrant-sized lists,
+// what's in effect now first and most severe first
+#[actix_web::test]
+async fn test_mbta_alerts() {
+    let upstream = MockUpstream::start().await;
+    let mut elevator = mbta_alert("elevator", "ELEVATOR_CLOSURE", 3, "South Station Elevator 804 unavailable", -3600, 86400);
+    elevator["attributes"]["informed_entity"][0]["facility"] = "804".into();
+    upstream.serve(
+        &format!("{}/alerts", MBTA_PATH),
+        Fixture::json(serde_json::json!({
+            "data": [
+                mbta_alert("minor", "DELAY", 3, "Minor Red Line delays", -600, 3600),
+                mbta_alert(
+                    "shuttle",
+                    "SHUTTLE",
+                    7,
+                    "Shuttle buses replace Red Line service between JFK/UMass and Ashmont for track work this weekend",
+                    3600,
+                    7200
+                ),
+                mbta_alert("over", "DELAY", 9, "Earlier delays", -7200, -60),
+                mbta_alert("severe", "DELAY", 7, "Red Line delays of about 20 minutes", -300, 3600),
+                elevator
+            ]
+        })),
+    );
+
+    let (status, json) = get_json(upstream.app_config(), "/MBTA/alerts?station=South%20Station&route=Red").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["station"], "South Station");
+    let service = json["service_alerts"].as_array().unwrap();
+    assert_eq!(service.len(), 3);
+    assert_eq!(service[0], "Red Line delays of about 20 minutes");
+    assert_eq!(service[1], "Minor Red Line delays");
+    let upcoming = service[2].as_str().unwrap();
+    assert!(upcoming.starts_with("From ") && upcoming.contains(": Shuttle buses replace Red Line"));
+    assert!(upcoming.ends_with('…') && upcoming.chars().count() <= 80, "{}", upcoming);
+    assert_eq!(json["facility_alerts"], serde_json::json!(["South Station Elevator 804 unavailable"]));
+    assert_eq!(json["alerts"].as_array().unwrap().len(), 4);
+
+    let (status, json) = get_json(upstream.app_config(), "/MBTA/alerts").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["error"], "bad_request");
+}
+
+#[actix_web::test]
+async fn test_mbta_predictions_unavailable() {
+    let upstream = MockUpstream::start().await;
+    serve_south_station(&upstream);
+    upstream.serve(&format!("{}/predictions", MBTA_PATH), Fixture::Status(503));
+
+    let (status, json) = post_json(upstream.app_config(), "/MBTA", serde_json::json!({"station_name": "South Station"})).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(json["error"], "upstream_unavailable");
+}
+
+// Without an API key the board can run off MBTA's GTFS-RT feeds instead, in the same shape
+#[actix_web::test]
+async fn test_mbta_gtfs_rt_board() {
+    let mut cancelled = mbta_trip_update("red-9", "Red", &[("70079", Some(200)), ("70105", Some(1500))]);
+    cancelled.trip_update.as_mut().unwrap().trip.schedule_relationship = Some(ScheduleRelationship::Canceled as i32);
+    let trip_updates = FeedMessage {
+        entity: vec![
+            mbta_trip_update("red-0", "Red", &[("70079", Some(-130)), ("70105", Some(1200))]),
+            mbta_trip_update("red-1", "Red", &[("70079", Some(150)), ("70105", Some(1500))]),
+            mbta_trip_update("red-end", "Red", &[("70080", None)]),
+            mbta_trip_update("cr-801", "CR-Providence", &[("NEC-2287", Some(400)), ("NEC-2276", Some(700))]),
+            cancelled,
+        ],
+        ..Default::default()
+    };
+    let vehicle = |id: &str, trip_id: &str, stop_id: &str, status: VehicleStopStatus| FeedEntity {
+        id: id.to_string(),
+        vehicle: Some(VehiclePosition {
+            trip: Some(TripDescriptor {
+                trip_id: Some(trip_id.to_string()),
+                ..Default::default()
+            }),
+            stop_id: Some(stop_id.to_string()),
+            current_status: Some(status as i32),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let vehicles = FeedMessage {
+        entity: vec![
+            vehicle("R-5463", "red-1", "70079", VehicleStopStatus::StoppedAt),
+            vehicle("1634", "cr-801", "NEC-2276", VehicleStopStatus::InTransitTo),
+        ],
+        ..Default::default()
+    };
+    let upstream = MockUpstream::start().await;
+    upstream.serve(MBTA_TRIP_UPDATES_PATH, Fixture::protobuf(trip_updates.encode_to_vec()));
+    upstream.serve(MBTA_VEHICLE_POSITIONS_PATH, Fixture::protobuf(vehicles.encode_to_vec()));
+    let mut config = upstream.app_config();
+    config.mbta.source = MbtaSource::GtfsRt;
+
+    let (status, json) = post_json(config, "/MBTA", serde_json::json!({"station_name": "South Station"})).await;
+
+    assert_eq!(status, StatusCode::OK);
+    let next_trains: Vec<&str> = json["next_trains"].as_array().unwrap().iter().map(|train| train.as_str().unwrap()).collect();
+    assert_eq!(
+        next_trains,
+        [
+            "Red Line to Braintree - 2 min (Boarding)",
+            "Providence/Stoughton Line to Back Bay - 6 min",
+            "No data available",
+            "No data available"
+        ]
+    );
+    assert_eq!(json["passed_train"], "Red Line left 2 min ago");
+    assert_eq!(json["next_train_location"], "Train is at South Station");
+    assert_eq!(json["trains"][1]["vehicle"]["status"], "IN_TRANSIT_TO");
+    assert_eq!(json["trains"][1]["vehicle"]["text"], "Train is on the way to Back Bay");
+    assert_eq!(upstream.hits(&format!("{}/predictions", MBTA_PATH)), 0);
+}
+
+// Orange and Green Line trips match on their own platforms, not only Red Line ones
+#[actix_web::test]
+async fn test_mbta_gtfs_rt_other_lines() {
+    let trip_updates = FeedMessage {
+        entity: vec![
+            mbta_trip_update("orange-1", "Orange", &[("70020", Some(240)), ("70001", Some(1200))]),
+            mbta_trip_update("orange-2", "Orange", &[("70021", Some(420)), ("70036", Some(1500))]),
+            mbta_trip_update("red-1", "Red", &[("70077", Some(180)), ("70105", Some(1500))]),
+            mbta_trip_update("green-b-1", "Green-B", &[("70130", Some(300)), ("70106", Some(900))]),
+        ],
+        ..Default::default()
+    };
+    let upstream = MockUpstream::start().await;
+    // The Green Line train is at a stop this feed has no name for
+    let vehicles = FeedMessage {
+        entity: vec![FeedEntity {
+            id: "G-10001".to_string(),
+            vehicle: Some(VehiclePosition {
+                trip: Some(TripDescriptor {
+                    trip_id: Some("green-b-1".to_string()),
+                    ..Default::default()
+                }),
+                stop_id: Some("99999".to_string()),
+                current_status: Some(VehicleStopStatus::StoppedAt as i32),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    upstream.serve(MBTA_TRIP_UPDATES_PATH, Fixture::protobuf(trip_updates.encode_to_vec()));
+    upstream.serve(MBTA_VEHICLE_POSITIONS_PATH, Fixture::protobuf(vehicles.encode_to_vec()));
+    let mut config = upstream.app_config();
+    config.mbta.source = MbtaSource::GtfsRt;
+
+    let (status, json) = post_json(
+        config.clone(),
+        "/MBTA",
+        serde_json::json!({"station_name": "Downtown Crossing", "route": "Orange"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["next_trains"][0], "Orange Line to Forest Hills - 4 min");
+    assert_eq!(json["next_trains"][1], "Orange Line to Oak Grove - 7 min");
+    assert_eq!(json["next_trains"][2], "No data available");
+
+    let (status, json) = post_json(config, "/MBTA", serde_json::json!({"station_name": "Harvard Ave"})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["next_trains"][0], "Green Line B to Boston College - 5 min");
+    assert_eq!(json["next_trains"][1], "No data available");
+    assert_eq!(json["next_train_location"], "No location available");
+    assert!(json["trains"][0]["vehicle"].is_null());
+}
+
+// Vehicle positions are optional, trip updates are not
+#[actix_web::test]
+async fn test_mbta_gtfs_rt_feed_errors() {
+    let upstream = MockUpstream::start().await;
+    let trip_updates = FeedMessage {
+        entity: vec![mbta_trip_update("red-1", "Red", &[("70079", Some(150)), ("70105", Some(1500))])],
+        ..Default::default()
+    };
+    upstream.serve(MBTA_TRIP_UPDATES_PATH, Fixture::protobuf(trip_updates.encode_to_vec()));
+    upstream.serve(MBTA_VEHICLE_POSITIONS_PATH, Fixture::Status(503));
+    let mut config = upstream.app_config();
+    config.mbta.source = MbtaSource::GtfsRt;
+
+    let (status, json) = post_json(config.clone(), "/MBTA", serde_json::json!({"station_name": "South Station"})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["next_trains"][0], "Red Line to Braintree - 2 min");
+
+    let upstream = MockUpstream::start().await;
+    upstream.serve(MBTA_TRIP_UPDATES_PATH, Fixture::text("not a protobuf"));
+    config.mbta.trip_updates_url = upstream.url(MBTA_TRIP_UPDATES_PATH);
+
+    let (status, json) = post_json(config, "/MBTA", serde_json::json!({"station_name": "South Station"})).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(json["error"], "decode_failed");
+}
+
+fn caltrain_trip_update(trip_id: &str, route_id: &str, stop_id: &str, offset: i64) -> FeedEntity {
+    let mut entity = mbta_trip_update(trip_id, route_id, &[(stop_id, Some(offset))]);
+    entity.trip_update.as_mut().unwrap().trip.direction_id = None;
+    entity
+}
+
+// A configured agency reading from `gtfs`, with its feeds on the mock upstream
+fn caltrain_config(upstream: &MockUpstream, gtfs: String) -> AgencyConfig {
+    AgencyConfig {
+        name: Some("Caltrain".to_string()),
+        gtfs,
+        trip_updates_url: upstream.url(AGENCY_TRIP_UPDATES_PATH),
+        headers: HashMap::from([("x-api-key".to_string(), "caltrain-key".to_string())]),
+        ..AgencyConfig::default()
+    }
+}
+
+fn serve_caltrain_feed(upstream: &MockUpstream) {
+    let feed = FeedMessage {
+        entity: vec![
+            caltrain_trip_update("101", "L1", "70211", 150),
+            caltrain_trip_update("102", "L1", "70212", 300),
+            caltrain_trip_update("501", "B7", "70211", 630),
+            caltrain_trip_update("sh-1", "SH", "777403", 400),
+        ],
+        ..Default::default()
+    };
+    upstream.serve(AGENCY_TRIP_UPDATES_PATH, Fixture::protobuf(feed.encode_to_vec()));
+}
+
+// Any agency with static GTFS and GTFS-RT trip updates gets BART's board under /transit,
+// from an agency-neutral request
+#[actix_web::test]
+async fn test_transit_agency_board() {
+    let upstream = MockUpstream::start().await;
+    serve_caltrain_feed(&upstream);
+    let mut config = upstream.app_config();
+    config
+        .agencies
+        .insert("caltrain".to_string(), caltrain_config(&upstream, "tests/fixtures/caltrain_gtfs".to_string()));
+    let request = serde_json::json!({"station": "Mountain View", "direction": 0});
+
+    let (status, json) = post_json(config.clone(), "/transit/Caltrain", request).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["station"], "Mountain View");
+    assert_eq!(json["outbound_train"], "Local to San Francisco - 2 min (No delay info)");
+    assert_eq!(json["inbound_train_0"], "Baby Bullet to San Francisco - 10 min (No delay info)");
+    assert_eq!(json["inbound_train_1"], "No data available");
+    assert_eq!(json["trains"][0]["platform"], "NB");
+    // Caltrain doesn't promise BART's every-car access, so trips.txt decides
+    assert_eq!(json["trains"][0]["access"]["wheelchair_accessible"], true);
+    assert_eq!(upstream.header(AGENCY_TRIP_UPDATES_PATH, "x-api-key").as_deref(), Some("caltrain-key"));
+
+    // Stops without a parent station go by their id
+    let request = serde_json::json!({"station": "777403"});
+    let (status, json) = post_json(config.clone(), "/transit/caltrain", request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["outbound_train"], "[Bus] Community Shuttle to Mountain View - 6 min (No delay info)");
+    // Buses only match their own line here, BART's bus bridge rule doesn't apply
+    let request = serde_json::json!({"station": "777403", "line": "Local"});
+    let (status, json) = post_json(config.clone(), "/transit/caltrain", request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["outbound_train"], "No data available");
+
+    // No alerts feed configured, so nothing to report
+    let (status, json) = get_json(config.clone(), "/transit/caltrain/alerts?station=Mountain%20View").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["service_alerts"], serde_json::json!(["No service alerts"]));
+
+    let request = serde_json::json!({"station": "Mountain View", "direction": 0});
+    let (status, json) = post_json(config, "/transit/muni", request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["error"], "bad_request");
+}
+
+// Static GTFS published as a zip (of a folder, as some agencies do) at a URL, with the
+// configured timezone winning over agency.txt's
+#[actix_web::test]
+async fn test_transit_agency_gtfs_zip() {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for file in ["agency.txt", "stops.txt", "routes.txt", "trips.txt"] {
+        zip.start_file(format!("caltrain_gtfs/{}", file), zip::write::FileOptions::default()).unwrap();
+        zip.write_all(&std::fs::read(format!("tests/fixtures/caltrain_gtfs/{}", file)).unwrap()).unwrap();
+    }
+    let zip = zip.finish().unwrap().into_inner();
+
+    let upstream = MockUpstream::start().await;
+    serve_caltrain_feed(&upstream);
+    upstream.serve(
+        "/agency/gtfs.zip",
+        Fixture::Body {
+            content_type: "application/zip",
+            body: zip,
+        },
+    );
+    let mut config = upstream.app_config();
+    let mut caltrain = caltrain_config(&upstream, upstream.url("/agency/gtfs.zip"));
+    caltrain.timezone = Some("America/New_York".to_string());
+    config.agencies.insert("caltrain".to_string(), caltrain);
+    let request = serde_json::json!({"station": "Mountain View", "line": "Local", "direction": 0, "show_time_not_minutes": true});
+
+    let (status, json) = post_json(config.clone(), "/transit/caltrain", request.clone()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    let departure = json["trains"][0]["predicted_time"].as_i64().unwrap();
+    let eastern = chrono::DateTime::from_timestamp(departure, 0)
+        .unwrap()
+        .with_timezone(&chrono_tz::America::New_York)
+        .format("%-I:%M %p")
+        .to_string();
+    assert_eq!(json["trains"][0]["display_time"], eastern);
+    assert_eq!(json["inbound_train_0"], "No data available");
+    assert_eq!(upstream.header("/agency/gtfs.zip", "x-api-key").as_deref(), Some("caltrain-key"));
+
+    // The zip is only fetched once
+    post_json(config, "/transit/caltrain", request).await;
+    assert_eq!(upstream.hits("/agency/gtfs.zip"), 1);
+}
+
+fn walnut_creek_to_mountain_view() -> serde_json::Value {
+    serde_json::json!({
+        "legs": [
+            {"agency": "BART", "stop": "Walnut Creek", "line": "Yellow", "direction": 1},
+            {"agency": "caltrain", "stop": "Mountain View", "direction": 0}
+        ]
+    })
+}
+
+// BART and a configured agency merged into one time-sorted board
+#[actix_web::test]
+async fn test_commute_board() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(BART_TRIP_UPDATES_PATH, Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)));
+    serve_caltrain_feed(&upstream);
+    let mut config = upstream.app_config();
+    config
+        .agencies
+        .insert("caltrain".to_string(), caltrain_config(&upstream, "tests/fixtures/caltrain_gtfs".to_string()));
+
+    let (status, json) = post_json(config.clone(), "/commute", walnut_creek_to_mountain_view()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    let departures = json["departures"].as_array().unwrap();
+    let times: Vec<i64> = departures.iter().map(|departure| departure["departure_time"].as_i64().unwrap()).collect();
+    assert!(times.is_sorted(), "departures should be soonest first: {:?}", times);
+    let agencies: Vec<&str> = departures.iter().map(|departure| departure["agency"].as_str().unwrap()).collect();
+    assert_eq!(agencies.iter().filter(|agency| **agency == "BART").count(), 3);
+    assert_eq!(agencies.iter().filter(|agency| **agency == "Caltrain").count(), 2);
+    // Caltrain's southbound train and its bus stop aren't part of this commute
+    assert!(departures.iter().all(|departure| departure["direction_id"] == if departure["agency"] == "BART" { 1 } else { 0 }));
+    assert!(departures.iter().all(|departure| departure["line"] != "Community Shuttle"));
+    assert_eq!(json["next_departures"].as_array().unwrap().len(), 4);
+    assert_eq!(json["next_departures"][1], "Caltrain: Local to San Francisco - 2 min (No delay info)");
+    assert_eq!(json["next_departures"][2], "Caltrain: Baby Bullet to San Francisco - 10 min (No delay info)");
+    assert_eq!(departures[3]["summary"], "BART: Yellow to San Francisco International Airport - 12 min (3 min late)");
+    assert_eq!(json["unavailable"], serde_json::json!([]));
+
+    // Asking for less trims the merged list
+    let mut body = walnut_creek_to_mountain_view();
+    body["limit"] = 2.into();
+    let (_, json) = post_json(config, "/commute", body).await;
+    assert_eq!(json["departures"].as_array().unwrap().len(), 2);
+    assert_eq!(json["next_departures"][2], "No data available");
+}
+
+// One agency down leaves the rest of the commute on screen; a typo in a leg doesn't
+#[actix_web::test]
+async fn test_commute_leg_errors() {
+    let upstream = MockUpstream::start().await;
+    upstream.serve(BART_TRIP_UPDATES_PATH, Fixture::protobuf(bart_feed_fixture("1741104000.pb", 30)));
+    upstream.serve(AGENCY_TRIP_UPDATES_PATH, Fixture::Status(503));
+    let mut config = upstream.app_config();
+    config
+        .agencies
+        .insert("caltrain".to_string(), caltrain_config(&upstream, "tests/fixtures/caltrain_gtfs".to_string()));
+
+    let (status, json) = post_json(config.clone(), "/commute", walnut_creek_to_mountain_view()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["departures"].as_array().unwrap().len(), 3);
+    assert_eq!(
+        json["unavailable"],
+        serde_json::json!(["caltrain Mountain View: Live data is unavailable right now"])
+    );
+    assert!(json["refresh"]["refresh_in_seconds"].as_i64().unwrap() <= 60);
+
+    let mut body = walnut_creek_to_mountain_view();
+    body["legs"][1]["agency"] = "muni".into();
+    let (status, json) = post_json(config.clone(), "/commute", body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["error"], "bad_request");
+
+    let mut body = walnut_creek_to_mountain_view();
+    body["legs"][0]["stop"] = "Atlantis".into();
+    let (status, json) = post_json(config.clone(), "/commute", body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["error"], "station_not_found");
+
+    let (status, _) = post_json(config, "/commute", serde_json::json!({"legs": []})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// The client resolves relationships and included resources into typed fields
+#[actix_web::test]
+async fn test_mbta_client_included_resources() {
+    let upstream = MockUpstream::start().await;
+    serve_south_station(&upstream);
+
+    let client = MbtaClient::new(&upstream.app_config());
+    let predictions = client
+        .predictions(&[("filter[stop]", "place-sstat"), ("include", "route,trip")])
+        .await
+        .unwrap();
+
+    let cr = predictions.data.iter().find(|prediction| prediction.trip_id.as_deref() == Some("cr-801")).unwrap();
+    assert_eq!(cr.route_id.as_deref(), Some("CR-Providence"));
+    assert_eq!(cr.stop_id.as_deref(), Some("70079"));
+    assert_eq!(cr.status.as_deref(), Some("On time"));
+    assert!(cr.departure_time.is_some());
+    assert!(predictions.data.iter().any(|prediction| prediction.departure_time.is_none()));
+    assert_eq!(predictions.included.routes["CR-Providence"].name(), "Providence/Stoughton Line");
+    assert_eq!(predictions.included.routes["Red"].route_type, 1);
+    assert_eq!(predictions.included.trips["cr-801"].headsign, "Providence");
+}
+
+// A configured API key goes out as the x-api-key header
+#[actix_web::test]
+async fn test_mbta_client_api_key() {
+    let upstream = MockUpstream::start().await;
+    serve_south_station(&upstream);
+    let mut config = upstream.app_config();
+    config.mbta.api_key = Some("test-key".to_string());
+
+    MbtaClient::new(&config).stops(&[]).await.unwrap();
+
+    assert_eq!(upstream.header(&format!("{}/stops", MBTA_PATH), "x-api-key").as_deref(), Some("test-key"));
+}
+
+// A 429 is retried after backing off, and given up on once the retries run out
+#[actix_web::test]
+async fn test_mbta_client_rate_limit_backoff() {
+    let upstream = MockUpstream::start().await;
+    let alerts_path = format!("{}/alerts", MBTA_PATH);
+    upstream.serve(
+        &alerts_path,
+        Fixture::Sequence(vec![
+            Fixture::Status(429),
+            Fixture::json(serde_json::json!({
+                "data": [{
+                    "type": "alert",
+                    "id": "601",
+                    "attributes": {
+                        "header": "Red Line delays of about 10 minutes",
+                        "effect": "DELAY",
+                        "severity": 3,
+                        "active_period": [{"start": "2025-03-04T06:00:00-05:00", "end": null}],
+                        "informed_entity": [{"route": "Red", "route_type": 1, "activities": ["BOARD", "RIDE"]}]
+                    }
+                }]
+            })),
+        ]),
+    );
+
+    let alerts = MbtaClient::new(&upstream.app_config()).alerts(&[]).await.unwrap();
+
+    assert_eq!(upstream.hits(&alerts_path), 2);
+    assert_eq!(alerts.data[0].effect, "DELAY");
+    assert_eq!(alerts.data[0].informed_entity[0].route.as_deref(), Some("Red"));
+    assert_eq!(alerts.data[0].active_period[0].start, Some(1741086000));
+    assert_eq!(alerts.data[0].active_period[0].end, None);
+
+    let vehicles_path = format!("{}/vehicles", MBTA_PATH);
+    upstream.serve(&vehicles_path, Fixture::Status(429));
+    let mut config = upstream.app_config();
+    config.mbta.rate_limit_retries = 1;
+
+    let error = MbtaClient::new(&config).vehicles(&[]).await.unwrap_err();
+
+    assert_eq!(error.code(), "upstream_unavailable");
+    assert_eq!(upstream.hits(&vehicles_path), 2);
+}